@@ -0,0 +1,82 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Every file in here besides `mod.rs` declares one interface
+const SIGNAL_DIR: &str = "src/interface/signal";
+const INTERFACE_ATTRIBUTE: &str = "#[harmony_interface";
+
+/// Same as the `#[harmony_interface]` macro, which names the query after the declared module
+fn to_pascal_case(s: &str) -> String {
+    s.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Lines of the `interfaces!` invocation declaring the interface of a file, copying any `#![cfg]`
+/// the file is gated behind
+fn declare_interface(path: &Path) -> String {
+    let module = path.file_stem().unwrap().to_string_lossy();
+    let source = fs::read_to_string(path).unwrap();
+
+    let declared = source
+        .find(INTERFACE_ATTRIBUTE)
+        .and_then(|start| {
+            source[start..]
+                .find("\nmod ")
+                .map(|found| start + found + 5)
+        })
+        .unwrap_or_else(|| panic!("{:?} has no `#[harmony_interface] mod`", path));
+    let declared: String = source[declared..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+
+    let mut lines: Vec<String> = source
+        .lines()
+        .map(str::trim)
+        .filter_map(|line| line.strip_prefix("#![cfg("))
+        .map(|cfg| format!("    #[cfg({}", cfg))
+        .collect();
+    lines.push(format!(
+        "    {}: {}Query,",
+        module,
+        to_pascal_case(&declared)
+    ));
+    lines.join("\n")
+}
+
+/// Lists the interfaces for `interface/signal/mod.rs`, so adding one only takes its own file
+fn main() {
+    println!("cargo:rerun-if-changed={}", SIGNAL_DIR);
+
+    let mut paths: Vec<_> = fs::read_dir(SIGNAL_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rs"))
+        .filter(|path| path.file_stem().is_some_and(|stem| stem != "mod"))
+        .collect();
+    paths.sort();
+
+    let interfaces: Vec<_> = paths.iter().map(|path| declare_interface(path)).collect();
+    let generated = format!(
+        "macro_rules! with_interfaces {{
+    ($macro:ident) => {{
+        $macro! {{
+{}
+        }}
+    }};
+}}
+",
+        interfaces.join("\n")
+    );
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("interfaces.rs"), generated).unwrap();
+}
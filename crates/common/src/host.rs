@@ -0,0 +1,310 @@
+//! Functions provided by Harmony core that wraps can call back into.
+//!
+//! On `wasm32` these are imported from the host under the [`HOST_MODULE`] namespace. Other targets
+//! have no host to talk to, so each function falls back to a plain std implementation.
+
+use crate::interface::{DataReference, HarmonySignal, WrapError, WrapKey};
+use bincode::{Decode, Encode};
+
+/// Import namespace of the host functions. Bumped whenever a function signature changes.
+pub const HOST_MODULE: &str = "hmny_host_v1";
+
+/// Returned by the stream host functions when a stream doesn't exist or could not be read
+pub const STREAM_ERROR: u64 = u64::MAX;
+
+#[derive(Clone, Copy, Decode, Encode, PartialEq, Debug, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn from_u32(level: u32) -> Option<Self> {
+        match level {
+            0 => Some(Self::Error),
+            1 => Some(Self::Warn),
+            2 => Some(Self::Info),
+            3 => Some(Self::Debug),
+            4 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn as_u32(&self) -> u32 {
+        match self {
+            Self::Error => 0,
+            Self::Warn => 1,
+            Self::Info => 2,
+            Self::Debug => 3,
+            Self::Trace => 4,
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod ffi {
+    // Must be kept in sync with HOST_MODULE
+    #[link(wasm_import_module = "hmny_host_v1")]
+    extern "C" {
+        pub fn log(level: u32, message_ptr: u64, message_len: u64);
+        pub fn log_record(record_ptr: u64, record_len: u64);
+        pub fn report_panic(record_ptr: u64, record_len: u64);
+        pub fn now() -> u64;
+        pub fn random(buffer_ptr: u64, buffer_len: u64);
+        pub fn read_stream(stream_id: u64, buffer_ptr: u64, buffer_len: u64) -> u64;
+        pub fn open_reference(reference_ptr: u64, reference_len: u64) -> u64;
+        pub fn close_stream(stream_id: u64);
+        pub fn signal_wrap(
+            query_id: u64,
+            version: u32,
+            key_ptr: u64,
+            key_len: u64,
+            signal_ptr: u64,
+            signal_len: u64,
+        ) -> u64;
+    }
+}
+
+/// Log a message through the host's logger
+pub fn log(level: LogLevel, message: &str) {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        ffi::log(
+            level.as_u32(),
+            message.as_ptr() as u64,
+            message.len() as u64,
+        )
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("[{:?}] {}", level, message);
+}
+
+/// A log message and where it was logged from. Usually created through [`wrap_info!`](crate::wrap_info) and friends
+#[derive(Clone, Decode, Encode, PartialEq, Debug, Eq)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    pub message: String,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// Log a record through the host's logger, which shows it under the wrap's name
+pub fn log_record(record: &LogRecord) {
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Fall back to a plain message rather than losing it
+        match bincode::encode_to_vec(record, bincode::config::standard()) {
+            Ok(record) => unsafe { ffi::log_record(record.as_ptr() as u64, record.len() as u64) },
+            Err(_) => log(record.level, &record.message),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    match (&record.file, record.line) {
+        (Some(file), Some(line)) => {
+            eprintln!(
+                "[{:?}] {} ({}:{})",
+                record.level, record.message, file, line
+            )
+        }
+        _ => eprintln!("[{:?}] {}", record.level, record.message),
+    }
+}
+
+/// Report panics to the host before the wrap traps, so it can tell why. Installed by `define_wrap`
+pub fn install_panic_hook() {
+    #[cfg(target_arch = "wasm32")]
+    {
+        static INSTALLED: std::sync::Once = std::sync::Once::new();
+        INSTALLED.call_once(|| {
+            std::panic::set_hook(Box::new(|info| {
+                let payload = info.payload();
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "Box<dyn Any>".into());
+
+                let record = LogRecord {
+                    level: LogLevel::Error,
+                    message: format!("panicked: {}", message),
+                    module_path: None,
+                    file: info.location().map(|location| location.file().into()),
+                    line: info.location().map(|location| location.line()),
+                };
+                match bincode::encode_to_vec(&record, bincode::config::standard()) {
+                    Ok(record) => unsafe {
+                        ffi::report_panic(record.as_ptr() as u64, record.len() as u64)
+                    },
+                    Err(_) => log(record.level, &record.message),
+                }
+            }));
+        });
+    }
+}
+
+/// Milliseconds elapsed since the unix epoch
+pub fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        ffi::now()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Fill the buffer with random bytes provided by the host
+pub fn random_bytes(buffer: &mut [u8]) {
+    #[cfg(target_arch = "wasm32")]
+    unsafe {
+        ffi::random(buffer.as_mut_ptr() as u64, buffer.len() as u64)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::hash::{BuildHasher, Hasher};
+
+        // RandomState is seeded randomly by std, which is good enough outside of wasm
+        for chunk in buffer.chunks_mut(8) {
+            let random = std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish()
+                .to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+}
+
+pub fn random_u64() -> u64 {
+    let mut buffer = [0; 8];
+    random_bytes(&mut buffer);
+    u64::from_le_bytes(buffer)
+}
+
+/// Send a signal to the wraps of a key, through the host
+///
+/// Needs the [`Capability::SignalWraps`](crate::interface::Capability::SignalWraps) capability. Wraps busy handling a signal, such as the one
+/// sending it, are skipped
+pub fn signal<Signal: HarmonySignal>(
+    key: &WrapKey,
+    signal: Signal,
+) -> Result<Signal::ResponseType, WrapError> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let config = bincode::config::standard();
+        let key = bincode::encode_to_vec(key, config)
+            .map_err(|error| WrapError::EncodeFailed(format!("{}", error)))?;
+        let signal = bincode::encode_to_vec(signal, config)
+            .map_err(|error| WrapError::EncodeFailed(format!("{}", error)))?;
+
+        let stream_id = unsafe {
+            ffi::signal_wrap(
+                Signal::QUERY_ID,
+                Signal::VERSION,
+                key.as_ptr() as u64,
+                key.len() as u64,
+                signal.as_ptr() as u64,
+                signal.len() as u64,
+            )
+        };
+        let mut response = Vec::new();
+        std::io::Read::read_to_end(&mut StreamReader::new(stream_id), &mut response)
+            .map_err(|error| WrapError::Other(format!("Could not read response: {}", error)))?;
+
+        let (response, _) =
+            bincode::decode_from_slice::<Result<Signal::ResponseType, WrapError>, _>(
+                &response, config,
+            )
+            .map_err(|error| WrapError::DecodeFailed(format!("{}", error)))?;
+        response
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = (key, signal);
+        Err(WrapError::CapabilityDenied(
+            crate::interface::Capability::SignalWraps,
+        ))
+    }
+}
+
+/// Reads a [`DataType::Stream`](crate::interface::DataType::Stream) held by the host, closing it once dropped
+pub struct StreamReader {
+    stream_id: u64,
+}
+
+impl StreamReader {
+    pub fn new(stream_id: u64) -> Self {
+        Self { stream_id }
+    }
+
+    pub fn stream_id(&self) -> u64 {
+        self.stream_id
+    }
+
+    /// Ask the host for a stream over referenced data
+    pub fn open(reference: &DataReference) -> Option<Self> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let reference = bincode::encode_to_vec(reference, bincode::config::standard()).ok()?;
+            let stream_id =
+                unsafe { ffi::open_reference(reference.as_ptr() as u64, reference.len() as u64) };
+            (stream_id != STREAM_ERROR).then(|| Self::new(stream_id))
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = reference;
+            None
+        }
+    }
+}
+
+impl std::io::Read for StreamReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let read = unsafe {
+                ffi::read_stream(
+                    self.stream_id,
+                    buffer.as_mut_ptr() as u64,
+                    buffer.len() as u64,
+                )
+            };
+            if read == STREAM_ERROR {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "stream could not be read",
+                ));
+            }
+            Ok(read as usize)
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = buffer;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "streams are only available to wraps",
+            ))
+        }
+    }
+}
+
+impl Drop for StreamReader {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        unsafe {
+            ffi::close_stream(self.stream_id)
+        }
+    }
+}
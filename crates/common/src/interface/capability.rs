@@ -0,0 +1,62 @@
+use bincode::{Decode, Encode};
+use std::fmt;
+use std::str::FromStr;
+
+/// A privilege a wrap must declare in its metadata, and the user must approve, before the host
+/// functions behind it can be called
+#[derive(Clone, Copy, Decode, Encode, PartialEq, Debug, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    /// Keep data around between runs
+    Storage,
+    /// Make requests to other machines
+    Network,
+    /// Read from and write to the clipboard
+    Clipboard,
+    /// Send signals to other wraps
+    SignalWraps,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 4] = [
+        Self::Storage,
+        Self::Network,
+        Self::Clipboard,
+        Self::SignalWraps,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Storage => "storage",
+            Self::Network => "network",
+            Self::Clipboard => "clipboard",
+            Self::SignalWraps => "signal_wraps",
+        }
+    }
+
+    /// What granting the capability allows, worded for users deciding whether to
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Storage => "Store data on this device",
+            Self::Network => "Access the network",
+            Self::Clipboard => "Read and change the clipboard",
+            Self::SignalWraps => "Communicate with other wraps",
+        }
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|capability| capability.as_str() == name)
+            .ok_or_else(|| format!("Unknown capability {:?}", name))
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
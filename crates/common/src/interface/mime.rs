@@ -0,0 +1,295 @@
+use bincode::{Decode, Encode};
+use std::fmt;
+use std::str::FromStr;
+
+/// Non-standard names still found in the wild, and the type they stand for
+const ALIASES: &[(&str, &str)] = &[
+    ("text/x-markdown", "text/markdown"),
+    ("application/javascript", "text/javascript"),
+    ("application/x-javascript", "text/javascript"),
+    ("text/xml", "application/xml"),
+    ("image/jpg", "image/jpeg"),
+    ("application/x-gzip", "application/gzip"),
+];
+
+/// Magic bytes at the start of a file, and the type they identify
+const MAGIC_BYTES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\0asm", "application/wasm"),
+];
+
+/// Markup prefixes of text files, matched case insensitively after leading whitespace
+const TEXT_PREFIXES: &[(&str, &str)] = &[
+    ("<!doctype html", "text/html"),
+    ("<html", "text/html"),
+    ("<svg", "image/svg+xml"),
+    ("<?xml", "application/xml"),
+];
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("txt", "text/plain"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("xml", "application/xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("wasm", "application/wasm"),
+];
+
+#[derive(Clone, PartialEq, Debug, Eq)]
+pub enum MimeError {
+    Empty,
+    MissingSubtype(String),
+    InvalidParameter(String),
+}
+
+/// A mime type such as `text/markdown; charset=utf-8`
+///
+/// Type, subtype and parameter names are lowercase, and known aliases are resolved when parsing.
+/// Either half may be `*` to match any type, such as `text/*` or `*/*`
+#[derive(Clone, Decode, Encode, PartialEq, Debug, Eq, Hash)]
+pub struct MimeType {
+    type_: String,
+    subtype: String,
+    parameters: Vec<(String, String)>,
+}
+
+impl MimeType {
+    pub fn new(type_: &str, subtype: &str) -> Self {
+        Self {
+            type_: type_.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters: vec![],
+        }
+        .resolve_alias()
+    }
+
+    /// Matches any mime type
+    pub fn any() -> Self {
+        Self::new("*", "*")
+    }
+
+    pub fn parse(mime_type: &str) -> Result<Self, MimeError> {
+        let mut parts = mime_type.split(';').map(str::trim);
+        let essence = parts.next().filter(|essence| !essence.is_empty());
+        let (type_, subtype) = essence
+            .ok_or(MimeError::Empty)?
+            .split_once('/')
+            .filter(|(type_, subtype)| !type_.is_empty() && !subtype.is_empty())
+            .ok_or_else(|| MimeError::MissingSubtype(mime_type.into()))?;
+
+        let mut parsed = Self::new(type_.trim(), subtype.trim());
+        for parameter in parts.filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter
+                .split_once('=')
+                .ok_or_else(|| MimeError::InvalidParameter(parameter.into()))?;
+            parsed = parsed.with_parameter(name.trim(), value.trim().trim_matches('"'));
+        }
+        Ok(parsed)
+    }
+
+    pub fn with_parameter(mut self, name: &str, value: &str) -> Self {
+        self.parameters
+            .push((name.to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    fn resolve_alias(mut self) -> Self {
+        let essence = self.essence();
+        if let Some((_, canonical)) = ALIASES.iter().find(|(alias, _)| *alias == essence) {
+            let (type_, subtype) = canonical.split_once('/').unwrap();
+            self.type_ = type_.into();
+            self.subtype = subtype.into();
+        }
+        self
+    }
+
+    pub fn type_(&self) -> &str {
+        &self.type_
+    }
+
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(parameter, _)| parameter.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The type and subtype without any parameters, such as `text/markdown`
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.type_, self.subtype)
+    }
+
+    pub fn is_wildcard(&self) -> bool {
+        self.type_ == "*" || self.subtype == "*"
+    }
+
+    /// Whether this type, used as a pattern, accepts `other`. Parameters of the pattern must all be
+    /// present in `other`, while extra parameters of `other` are ignored
+    pub fn matches(&self, other: &MimeType) -> bool {
+        let type_matches = self.type_ == "*" || self.type_ == other.type_;
+        let subtype_matches = self.subtype == "*" || self.subtype == other.subtype;
+        type_matches
+            && subtype_matches
+            && self
+                .parameters
+                .iter()
+                .all(|(name, value)| other.parameter(name) == Some(value.as_str()))
+    }
+
+    /// How narrow a pattern is. More specific patterns should be preferred when several match
+    pub fn specificity(&self) -> u8 {
+        match (self.type_.as_str(), self.subtype.as_str()) {
+            ("*", _) => 0,
+            (_, "*") => 1,
+            _ => 2,
+        }
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_ascii_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, mime_type)| mime_type.parse().unwrap())
+    }
+
+    /// Identify data from its content alone, returning None if nothing gives it away
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if let Some((_, mime_type)) = MAGIC_BYTES
+            .iter()
+            .find(|(magic, _)| data.starts_with(magic))
+        {
+            return Some(mime_type.parse().unwrap());
+        }
+        if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+            return Some(Self::new("image", "webp"));
+        }
+
+        // Binary data that wasn't recognized above
+        let text = std::str::from_utf8(data)
+            .ok()
+            .filter(|text| !text.contains('\0'))?;
+        let trimmed = text.trim_start_matches('\u{feff}').trim_start();
+        let start = trimmed.get(..16).unwrap_or(trimmed).to_ascii_lowercase();
+        let mime_type = TEXT_PREFIXES
+            .iter()
+            .find(|(prefix, _)| start.starts_with(prefix))
+            .map_or("text/plain", |(_, mime_type)| mime_type);
+        Some(mime_type.parse().unwrap())
+    }
+
+    /// Best guess at the type of data whose type wasn't declared
+    ///
+    /// Magic bytes are trusted over the file extension, which is trusted over guessing from text
+    pub fn guess(file_name: Option<&str>, data: &[u8]) -> Self {
+        let sniffed = Self::sniff(data);
+        if let Some(sniffed) = sniffed.as_ref().filter(|sniffed| sniffed.type_ != "text") {
+            return sniffed.clone();
+        }
+
+        file_name
+            .and_then(|file_name| file_name.rsplit_once('.'))
+            .and_then(|(_, extension)| Self::from_extension(extension))
+            .or(sniffed)
+            .unwrap_or_else(|| Self::new("application", "octet-stream"))
+    }
+}
+
+impl FromStr for MimeType {
+    type Err = MimeError;
+
+    fn from_str(mime_type: &str) -> Result<Self, Self::Err> {
+        Self::parse(mime_type)
+    }
+}
+
+impl fmt::Display for MimeType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.type_, self.subtype)?;
+        for (name, value) in self.parameters.iter() {
+            write!(f, "; {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mime(mime_type: &str) -> MimeType {
+        mime_type.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = mime("Text/Markdown; charset=\"utf-8\"");
+        assert_eq!(parsed.essence(), "text/markdown");
+        assert_eq!(parsed.parameter("charset"), Some("utf-8"));
+        assert_eq!(parsed.to_string(), "text/markdown; charset=utf-8");
+
+        assert_eq!(mime("text/x-markdown"), mime("text/markdown"));
+        assert_eq!(
+            MimeType::parse("markdown"),
+            Err(MimeError::MissingSubtype("markdown".into()))
+        );
+        assert_eq!(MimeType::parse(""), Err(MimeError::Empty));
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(mime("text/*").matches(&mime("text/markdown; charset=utf-8")));
+        assert!(MimeType::any().matches(&mime("image/png")));
+        assert!(!mime("text/*").matches(&mime("image/png")));
+        assert!(!mime("text/plain; charset=utf-8").matches(&mime("text/plain")));
+        assert!(mime("text/markdown").specificity() > mime("text/*").specificity());
+    }
+
+    #[test]
+    fn test_guess() {
+        assert_eq!(
+            MimeType::guess(None, b"\x89PNG\r\n\x1a\n...."),
+            mime("image/png")
+        );
+        assert_eq!(
+            MimeType::guess(Some("a.png"), b"# Title"),
+            mime("image/png")
+        );
+        assert_eq!(
+            MimeType::guess(Some("README.md"), b"# Title"),
+            mime("text/markdown")
+        );
+        assert_eq!(
+            MimeType::guess(None, b"  <!DOCTYPE html><html>"),
+            mime("text/html")
+        );
+        assert_eq!(MimeType::guess(None, b"# Title"), mime("text/plain"));
+        assert_eq!(
+            MimeType::guess(None, b"\0\x01\x02"),
+            mime("application/octet-stream")
+        );
+    }
+}
@@ -1,8 +1,15 @@
 use bincode::{Decode, Encode};
+use semver::Version;
+
+mod capability;
+pub use capability::*;
 
 mod dom;
 pub use dom::*;
 
+mod mime;
+pub use mime::*;
+
 mod signal;
 pub use signal::*;
 
@@ -20,9 +27,29 @@ impl InterfaceVersion {
         Self(version.into())
     }
 
-    /// Whether or not the interface version of an wrap matches the current version
-    pub fn matches_own(&self) -> bool {
-        self.0 == INTERFACE_VERSION
+    pub fn parse(&self) -> Option<Version> {
+        Version::parse(&self.0).ok()
+    }
+
+    /// Whether a wrap built against this interface version can talk to a host built against `other`
+    ///
+    /// Follows cargo's caret rules in both directions: versions must share their major version, or
+    /// their minor version below 1.0, or their patch version below 0.1. Pre-release tags are ignored,
+    /// and individual queries are negotiated separately through [`CommonQuery::AskSupportedQueries`]
+    pub fn is_compatible_with(&self, other: &InterfaceVersion) -> bool {
+        match (self.parse(), other.parse()) {
+            (Some(a), Some(b)) => match (a.major, b.major) {
+                (0, 0) if a.minor == 0 && b.minor == 0 => a.patch == b.patch,
+                (0, 0) => a.minor == b.minor,
+                (a_major, b_major) => a_major == b_major,
+            },
+            _ => false,
+        }
+    }
+
+    /// Whether or not the interface version of an wrap is compatible with the current version
+    pub fn is_compatible_with_own(&self) -> bool {
+        self.is_compatible_with(&Self::new())
     }
 }
 
@@ -37,7 +64,39 @@ pub enum WrapType {
     None,
     Test,
     HomeScreen,
-    Mimetype(String),
+    /// Handles data of any type matched by the mime type, which may be a wildcard such as `text/*`
+    Mimetype(MimeType),
+}
+
+/// Which wraps a signal is routed to
+#[derive(Clone, Decode, Encode, PartialEq, Debug, Eq, Hash)]
+pub enum WrapKey {
+    HomeScreen,
+    Mimetype(MimeType),
+    Other(WrapType, String),
+}
+
+impl WrapKey {
+    /// Key for data whose type wasn't declared, guessed from its content and file name
+    pub fn for_data(file_name: Option<&str>, data: &[u8]) -> Self {
+        Self::Mimetype(MimeType::guess(file_name, data))
+    }
+
+    /// Whether a wrap loaded under this key can handle signals sent to `requested`
+    pub fn accepts(&self, requested: &WrapKey) -> bool {
+        match (self, requested) {
+            (Self::Mimetype(pattern), Self::Mimetype(mime_type)) => pattern.matches(mime_type),
+            _ => self == requested,
+        }
+    }
+
+    /// Wraps with a more specific key are preferred over catch-all ones
+    pub fn specificity(&self) -> u8 {
+        match self {
+            Self::Mimetype(pattern) => pattern.specificity(),
+            _ => u8::MAX,
+        }
+    }
 }
 
 #[derive(Clone, Decode, Encode, PartialEq, Debug, Eq)]
@@ -60,6 +119,15 @@ pub struct WrapMetdata {
     pub description: String,
     pub publisher: Publisher,
     pub interface_version: InterfaceVersion,
+    /// Privileges the wrap asks for. Only those the user approved are granted
+    pub capabilities: Vec<Capability>,
+    /// Path or url of an icon representing the wrap
+    pub icon: Option<String>,
+    pub homepage: Option<String>,
+    /// Mimetypes the wrap can handle, besides the one it is loaded for
+    pub supported_mimetypes: Vec<MimeType>,
+    /// Publisher verified by the host from the wrap's package signatures. Always set by the host, never by the wrap
+    pub verified_publisher: Option<Publisher>,
 }
 
 #[derive(Clone, Decode, Encode, PartialEq, Debug, Eq)]
@@ -68,6 +136,8 @@ pub enum WrapError {
     DecodeFailed(String),
     EncodeFailed(String),
     UnsupportedInterface(u64),
+    /// The wrap wasn't granted the capability needed for this
+    CapabilityDenied(Capability),
     Other(String),
 }
 
@@ -96,6 +166,14 @@ impl Publisher {
             signed_by,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn signed_by(&self) -> &[Publisher] {
+        &self.signed_by
+    }
 }
 
 impl Into<Publisher> for String {
@@ -115,3 +193,27 @@ impl Into<Publisher> for &str {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compatible(a: &str, b: &str) -> bool {
+        InterfaceVersion::from(a).is_compatible_with(&InterfaceVersion::from(b))
+    }
+
+    #[test]
+    fn test_interface_version_compatibility() {
+        assert!(compatible("1.2.3", "1.0.0"));
+        assert!(compatible("1.0.0", "1.9.1"));
+        assert!(!compatible("2.0.0", "1.0.0"));
+
+        assert!(compatible("0.3.1", "0.3.0"));
+        assert!(!compatible("0.3.0", "0.4.0"));
+
+        assert!(compatible("0.0.1-dev", "0.0.1"));
+        assert!(!compatible("0.0.1", "0.0.2"));
+
+        assert!(!compatible("not a version", "0.0.1"));
+    }
+}
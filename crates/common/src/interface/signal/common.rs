@@ -1,29 +1,89 @@
 use super::*;
+use crate::host;
 
 #[derive(Clone, Decode, Encode, PartialEq, Debug)]
 pub enum DataType {
     String(String),
+    Bytes(Vec<u8>),
+    /// Data too large to send in a single signal, read in chunks from the host
+    Stream {
+        stream_id: u64,
+        len: Option<u64>,
+    },
+    /// Data held by the host, only pulled in if the wrap needs it
+    Reference(DataReference),
 }
 
-#[derive(Clone, Decode, Encode, PartialEq, Debug)]
-pub enum CommonQuery {
-    AskMetadata,
-    Ping { message: String },
+#[derive(Clone, Decode, Encode, PartialEq, Debug, Eq, Hash)]
+pub enum DataReference {
+    /// Sha-256 hash of the content
+    Hash([u8; 32]),
+    Url(String),
 }
 
-#[derive(Clone, Decode, Encode, PartialEq, Debug)]
-pub enum CommonResponse {
-    Metadata(WrapMetdata),
-    Pong { response: String },
+impl DataType {
+    /// Read the whole payload, pulling it from the host if needed
+    pub fn into_bytes(self) -> Result<Vec<u8>, WrapError> {
+        let (mut reader, len) = match self {
+            Self::String(data) => return Ok(data.into_bytes()),
+            Self::Bytes(data) => return Ok(data),
+            Self::Stream { stream_id, len } => (host::StreamReader::new(stream_id), len),
+            Self::Reference(reference) => {
+                let reader = host::StreamReader::open(&reference).ok_or_else(|| {
+                    WrapError::Other(format!("Could not resolve {:?}", reference))
+                })?;
+                (reader, None)
+            }
+        };
+
+        let mut data = Vec::with_capacity(len.unwrap_or(0) as usize);
+        std::io::Read::read_to_end(&mut reader, &mut data)
+            .map_err(|error| WrapError::Other(format!("Could not read stream: {}", error)))?;
+        Ok(data)
+    }
 }
 
-pub type CommonResult = Result<CommonResponse, WrapError>;
+#[harmony_interface(version = 1, key = |key: WrapKey| key)]
+mod common {
+    pub enum Query {
+        AskMetadata,
+        Ping {
+            message: String,
+        },
+        AskSupportedQueries,
+        /// Ask for the wrap's state before it is replaced by a new build. Optional
+        Snapshot,
+        /// Hand a snapshot taken from the previous build over to the new one. Optional
+        Restore {
+            state: Vec<u8>,
+        },
+    }
 
-pub trait CommonInterface {
-    fn common_query(query: CommonQuery) -> CommonResult;
+    pub enum Response {
+        Metadata(WrapMetdata),
+        Pong { response: String },
+        SupportedQueries(Vec<SupportedQuery>),
+        Snapshot { state: Vec<u8> },
+        Restored,
+    }
 }
 
-impl HarmonySignal for CommonQuery {
-    type ResponseType = CommonResponse;
-    const QUERY_ID: u64 = 0;
+/// A query a wrap knows how to handle, and the version it was built against
+#[derive(Clone, Decode, Encode, PartialEq, Debug, Eq)]
+pub struct SupportedQuery {
+    pub query_id: u64,
+    pub version: u32,
+}
+
+impl SupportedQuery {
+    pub fn of<Signal: HarmonySignal>() -> Self {
+        Self {
+            query_id: Signal::QUERY_ID,
+            version: Signal::VERSION,
+        }
+    }
+
+    pub fn supports<Signal: HarmonySignal>(&self) -> bool {
+        *self == Self::of::<Signal>()
+    }
 }
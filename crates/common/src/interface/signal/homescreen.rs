@@ -1,22 +1,14 @@
-use super::*;
-
-#[derive(Clone, Decode, Encode, PartialEq, Debug)]
-pub enum HomescreenQuery {
-    AskHomeScreen,
-}
+#![cfg(feature = "homescreen")]
 
-#[derive(Clone, Decode, Encode, PartialEq, Debug)]
-pub enum HomescreenResponse {
-    HomeScreen { mime_type: String, data: DataType },
-}
-
-pub type HomescreenResult = Result<HomescreenResponse, WrapError>;
+use super::*;
 
-pub trait HomescreenInterface {
-    fn homescreen_query(query: HomescreenQuery) -> HomescreenResult;
-}
+#[harmony_interface(version = 2, key = || WrapKey::HomeScreen)]
+mod homescreen {
+    pub enum Query {
+        AskHomeScreen,
+    }
 
-impl HarmonySignal for HomescreenQuery {
-    type ResponseType = HomescreenResponse;
-    const QUERY_ID: u64 = 1;
+    pub enum Response {
+        HomeScreen { mime_type: MimeType, data: DataType },
+    }
 }
@@ -1,22 +1,14 @@
-use super::*;
-
-#[derive(Clone, Decode, Encode, PartialEq, Debug)]
-pub enum MimetypeQuery {
-    AskParse { data: DataType },
-}
+#![cfg(feature = "mimetype")]
 
-#[derive(Clone, Decode, Encode, PartialEq, Debug)]
-pub enum MimetypeResponse {
-    Dimension(dom::Dimension),
-}
-
-pub type MimetypeResult = Result<MimetypeResponse, WrapError>;
+use super::*;
 
-pub trait MimetypeInterface {
-    fn mimetype_query(query: MimetypeQuery) -> MimetypeResult;
-}
+#[harmony_interface(version = 1, key = |mime_type: MimeType| WrapKey::Mimetype(mime_type))]
+mod mimetype {
+    pub enum Query {
+        AskParse { data: DataType },
+    }
 
-impl HarmonySignal for MimetypeQuery {
-    type ResponseType = MimetypeResponse;
-    const QUERY_ID: u64 = 2;
+    pub enum Response {
+        Dimension(dom::Dimension),
+    }
 }
@@ -1,19 +1,76 @@
 use super::*;
+use hmny_macros::harmony_interface;
 
-mod common;
-pub use common::*;
+/// Declares every interface, and lets hosts handle all of their queries through [`visit_signals`]
+///
+/// Invoked by the build script with every file of this directory, so adding an interface only
+/// takes its own file. Files starting with `#![cfg(...)]` are only declared when it holds
+macro_rules! interfaces {
+    ($( $(#[$attr:meta])* $module:ident: $query:ident ),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            mod $module;
+            $(#[$attr])*
+            pub use $module::*;
+        )*
 
-pub trait HarmonySignal: Sized + Decode + Encode {
-    type ResponseType: Decode + Encode;
+        /// Visit the query of every interface
+        pub fn visit_signals(visitor: &mut impl SignalVisitor) {
+            $(
+                $(#[$attr])*
+                visitor.visit::<$query>();
+            )*
+        }
+    };
+}
+
+// Defines `with_interfaces!`, which passes every interface to a macro. Invoked here rather than
+// generated as is, since modules declared by included files are looked for next to them
+include!(concat!(env!("OUT_DIR"), "/interfaces.rs"));
+with_interfaces!(interfaces);
+
+pub trait HarmonySignal: Sized + Decode + Encode + Send + Sync + 'static {
+    type ResponseType: Decode + Encode + Send + Sync + 'static;
     const QUERY_ID: u64;
+    /// Bumped whenever the query or response changes in a way older wraps or hosts can't handle
+    const VERSION: u32;
+}
+
+/// Handles one query, for `define_wrap` to reach the interface traits generated by `#[harmony_interface]`
+pub trait HandleSignal<Signal: HarmonySignal> {
+    fn handle_signal(query: Signal) -> Result<Signal::ResponseType, WrapError>;
+}
+
+/// Delivers signals to wraps, for the clients generated by `#[derive(HarmonyClient)]`
+pub trait SignalSender {
+    type Error;
+
+    fn send<Signal: HarmonySignal>(
+        &mut self,
+        signal: Signal,
+    ) -> Result<Signal::ResponseType, Self::Error>;
+}
+
+/// Opens senders to the wraps of a key, such as the host's `Wraps`
+///
+/// Interfaces declared with a `key` add a method opening their client, like `wraps.mimetype(mime)`
+pub trait SignalRouter {
+    type Sender<'a>: SignalSender
+    where
+        Self: 'a;
+
+    fn sender(&mut self, key: WrapKey) -> Self::Sender<'_>;
+}
+
+/// Handles the query of each interface, for [`visit_signals`]
+pub trait SignalVisitor {
+    fn visit<Signal: HarmonySignal>(&mut self);
 }
 
-#[cfg(feature = "homescreen")]
-mod homescreen;
-#[cfg(feature = "homescreen")]
-pub use homescreen::*;
+/// Implemented for [`QueryRegistry`] once per query id by `#[derive(HarmonyClient)]`, so two queries
+/// sharing an id fail to compile
+#[doc(hidden)]
+pub trait QueryId<const ID: u64> {}
 
-#[cfg(feature = "mimetype")]
-mod mimetype;
-#[cfg(feature = "mimetype")]
-pub use mimetype::*;
+#[doc(hidden)]
+pub struct QueryRegistry;
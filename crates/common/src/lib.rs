@@ -1,7 +1,19 @@
+pub mod host;
 pub mod interface;
+mod log;
+pub mod memory;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+pub mod storage;
 
 pub mod prelude {
+    pub use super::host;
     pub use super::interface::*;
+    pub use super::memory;
+    #[cfg(not(target_arch = "wasm32"))]
+    pub use super::native;
+    pub use super::storage;
+    pub use crate::{wrap_debug, wrap_error, wrap_info, wrap_log, wrap_trace, wrap_warn};
     pub use hmny_macros::*;
 
     pub extern crate bincode;
@@ -0,0 +1,41 @@
+//! Macros logging through the host, recording where each message was logged from.
+//!
+//! Wraps compiled for `wasm32-unknown-unknown` can't print, so these are the way to debug them.
+
+#[macro_export]
+macro_rules! wrap_log {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::host::log_record(&$crate::host::LogRecord {
+            level: $level,
+            message: format!($($arg)+),
+            module_path: Some(module_path!().into()),
+            file: Some(file!().into()),
+            line: Some(line!()),
+        })
+    };
+}
+
+#[macro_export]
+macro_rules! wrap_error {
+    ($($arg:tt)+) => { $crate::wrap_log!($crate::host::LogLevel::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! wrap_warn {
+    ($($arg:tt)+) => { $crate::wrap_log!($crate::host::LogLevel::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! wrap_info {
+    ($($arg:tt)+) => { $crate::wrap_log!($crate::host::LogLevel::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! wrap_debug {
+    ($($arg:tt)+) => { $crate::wrap_log!($crate::host::LogLevel::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! wrap_trace {
+    ($($arg:tt)+) => { $crate::wrap_log!($crate::host::LogLevel::Trace, $($arg)+) };
+}
@@ -0,0 +1,54 @@
+//! Buffer exchange between Harmony core and wraps.
+//!
+//! The host requests a buffer through the `alloc` export generated by `define_wrap`, writes the
+//! input signal into it, and once the signal returns frees both the input buffer and the output
+//! buffer handed over by the wrap through the `dealloc` export.
+
+use std::alloc::{self, Layout};
+use std::ptr::NonNull;
+
+/// Allocate an uninitialized buffer of `len` bytes owned by the host until it is passed to [`dealloc`]
+pub fn alloc(len: u64) -> u64 {
+    // Zero sized allocations are not allowed, and are never freed
+    if len == 0 {
+        return NonNull::<u8>::dangling().as_ptr() as u64;
+    }
+
+    let layout = Layout::array::<u8>(len as usize).expect("Buffer too large");
+    let ptr = unsafe { alloc::alloc(layout) };
+    if ptr.is_null() {
+        alloc::handle_alloc_error(layout);
+    }
+    ptr as u64
+}
+
+/// Free a buffer previously returned by [`alloc`] or [`into_raw_buffer`]
+///
+/// # Safety
+///
+/// `ptr` and `len` must describe a buffer produced by this module that has not been freed yet
+pub unsafe fn dealloc(ptr: u64, len: u64) {
+    if len == 0 {
+        return;
+    }
+
+    let layout = Layout::array::<u8>(len as usize).expect("Buffer too large");
+    alloc::dealloc(ptr as *mut u8, layout);
+}
+
+/// Hand ownership of a buffer over to the host, packing both its pointer and length into a single u64
+pub fn into_raw_buffer(buffer: Vec<u8>) -> u64 {
+    // A boxed slice has no spare capacity, so it can be freed knowing only its length
+    let buffer = buffer.into_boxed_slice();
+    let len = buffer.len() as u64;
+    let ptr = Box::into_raw(buffer) as *mut u8 as u64;
+    pack_ptr_len(ptr, len)
+}
+
+pub fn pack_ptr_len(ptr: u64, len: u64) -> u64 {
+    (ptr << 32) | (len & 0xFFFFFFFF)
+}
+
+pub fn unpack_ptr_len(packed: u64) -> (u64, u64) {
+    (packed >> 32, packed & 0xFFFFFFFF)
+}
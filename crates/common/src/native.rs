@@ -0,0 +1,13 @@
+//! Wraps linked into the host as plain Rust libraries, so they can be stepped through with a native
+//! debugger.
+//!
+//! Outside of wasm, `define_wrap` generates a `native_wrap()` function returning a [`NativeWrap`].
+//! Host functions then fall back to their std implementations, and wraps run without any limits.
+
+/// A wrap's signal handler, as generated by `define_wrap`
+#[derive(Clone, Copy, Debug)]
+pub struct NativeWrap {
+    pub name: &'static str,
+    /// Takes a query id and an encoded signal, returning the encoded response like the wasm `signal` export
+    pub signal: fn(u64, &[u8]) -> Vec<u8>,
+}
@@ -0,0 +1,129 @@
+//! Key-value storage kept by the host for each wrap, surviving reloads and restarts.
+//!
+//! Needs the [`Capability::Storage`](crate::interface::Capability::Storage) capability. Wraps only
+//! ever see their own entries, and the host caps the combined size of keys and values.
+//!
+//! Outside of wasm, entries are kept in memory for the lifetime of the process.
+
+use bincode::{Decode, Encode};
+
+/// Longest key allowed, in bytes
+pub const MAX_KEY_LEN: usize = 256;
+
+#[derive(Clone, Decode, Encode, PartialEq, Debug, Eq)]
+pub enum StorageError {
+    CapabilityDenied,
+    /// Writing would take the wrap's entries past its quota, in bytes
+    QuotaExceeded {
+        used: u64,
+        quota: u64,
+    },
+    /// Keys must be between 1 and [`MAX_KEY_LEN`] bytes long
+    InvalidKey,
+    /// The host could not read or write the store
+    Unavailable(String),
+}
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+pub fn check_key(key: &str) -> StorageResult<()> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return Err(StorageError::InvalidKey);
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+mod ffi {
+    // Must be kept in sync with HOST_MODULE
+    #[link(wasm_import_module = "hmny_host_v1")]
+    extern "C" {
+        pub fn storage_get(key_ptr: u64, key_len: u64) -> u64;
+        pub fn storage_put(key_ptr: u64, key_len: u64, value_ptr: u64, value_len: u64) -> u64;
+        pub fn storage_delete(key_ptr: u64, key_len: u64) -> u64;
+        pub fn storage_list(prefix_ptr: u64, prefix_len: u64) -> u64;
+    }
+}
+
+/// Every storage host function returns a stream holding an encoded [`StorageResult`]
+#[cfg(target_arch = "wasm32")]
+fn read_result<T: Decode>(stream_id: u64) -> StorageResult<T> {
+    let mut bytes = Vec::new();
+    std::io::Read::read_to_end(&mut crate::host::StreamReader::new(stream_id), &mut bytes)
+        .map_err(|error| StorageError::Unavailable(format!("{}", error)))?;
+    let (result, _) = bincode::decode_from_slice(&bytes, bincode::config::standard())
+        .map_err(|error| StorageError::Unavailable(format!("{}", error)))?;
+    result
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+static ENTRIES: std::sync::Mutex<std::collections::BTreeMap<String, Vec<u8>>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+#[cfg(not(target_arch = "wasm32"))]
+fn entries() -> std::sync::MutexGuard<'static, std::collections::BTreeMap<String, Vec<u8>>> {
+    ENTRIES.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+pub fn get(key: &str) -> StorageResult<Option<Vec<u8>>> {
+    check_key(key)?;
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        read_result(unsafe { ffi::storage_get(key.as_ptr() as u64, key.len() as u64) })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    Ok(entries().get(key).cloned())
+}
+
+/// Store a value, replacing any previous value for the key
+pub fn put(key: &str, value: &[u8]) -> StorageResult<()> {
+    check_key(key)?;
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        read_result(unsafe {
+            ffi::storage_put(
+                key.as_ptr() as u64,
+                key.len() as u64,
+                value.as_ptr() as u64,
+                value.len() as u64,
+            )
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        entries().insert(key.into(), value.into());
+        Ok(())
+    }
+}
+
+/// Remove a key, returning whether it existed
+pub fn delete(key: &str) -> StorageResult<bool> {
+    check_key(key)?;
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        read_result(unsafe { ffi::storage_delete(key.as_ptr() as u64, key.len() as u64) })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    Ok(entries().remove(key).is_some())
+}
+
+/// Every stored key starting with the prefix, in order
+pub fn list(prefix: &str) -> StorageResult<Vec<String>> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        read_result(unsafe { ffi::storage_list(prefix.as_ptr() as u64, prefix.len() as u64) })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    Ok(entries()
+        .keys()
+        .filter(|key| key.starts_with(prefix))
+        .cloned()
+        .collect())
+}
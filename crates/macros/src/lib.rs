@@ -1,42 +1,149 @@
 use proc_macro::TokenStream;
+use proc_macro2::Span;
 use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Expr, ExprMatch, Fields, Ident, Token};
+use syn::punctuated::Punctuated;
+use syn::{
+    bracketed, parse_macro_input, DeriveInput, Expr, ExprMatch, Fields, Ident, LitStr, Token,
+};
+
+/// Every key `define_wrap` accepts
+const WRAP_KEYS: &[&str] = &[
+    "publisher",
+    "wrap_type",
+    "description",
+    "capabilities",
+    "icon",
+    "homepage",
+    "supported_mimetypes",
+    "common_query",
+];
 
 struct WrapDefinition {
     publisher: Expr,
     wrap_type: Expr,
+    /// Defaults to the package description
+    description: Option<LitStr>,
+    capabilities: Option<Expr>,
+    icon: Option<LitStr>,
+    /// Defaults to the package homepage
+    homepage: Option<LitStr>,
+    /// Type and subtype of each mimetype
+    supported_mimetypes: Vec<(String, String)>,
     common_query_matcher: Option<ExprMatch>,
 }
 
+/// Store the value of a key, refusing to set it twice
+fn set_once<T>(slot: &mut Option<T>, key: &Ident, value: T) -> syn::Result<()> {
+    if slot.replace(value).is_some() {
+        return Err(syn::Error::new(
+            key.span(),
+            format!("`{}` is set more than once", key),
+        ));
+    }
+    Ok(())
+}
+
+fn parse_non_empty(input: syn::parse::ParseStream) -> syn::Result<LitStr> {
+    let value: LitStr = input.parse()?;
+    if value.value().trim().is_empty() {
+        return Err(syn::Error::new(value.span(), "Must not be empty"));
+    }
+    Ok(value)
+}
+
+fn parse_homepage(input: syn::parse::ParseStream) -> syn::Result<LitStr> {
+    let homepage = parse_non_empty(input)?;
+    let url = homepage.value();
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+        return Err(syn::Error::new(
+            homepage.span(),
+            "The homepage must be an http or https url",
+        ));
+    }
+    Ok(homepage)
+}
+
+/// A list of mimetypes such as `["text/markdown", "text/x-markdown"]`
+fn parse_mimetypes(input: syn::parse::ParseStream) -> syn::Result<Vec<(String, String)>> {
+    let content;
+    bracketed!(content in input);
+    Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?
+        .into_iter()
+        .map(|mimetype| {
+            let value = mimetype.value();
+            let is_token = |part: &str| {
+                !part.is_empty() && !part.contains(|c: char| c.is_whitespace() || c == ';')
+            };
+            match value.split_once('/') {
+                Some((type_, subtype)) if is_token(type_) && is_token(subtype) => {
+                    Ok((type_.to_lowercase(), subtype.to_lowercase()))
+                }
+                _ => Err(syn::Error::new(
+                    mimetype.span(),
+                    "Expected a mimetype such as \"text/markdown\", without parameters",
+                )),
+            }
+        })
+        .collect()
+}
+
 impl syn::parse::Parse for WrapDefinition {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        input.parse::<Ident>()?;
-        input.parse::<Token![:]>()?;
-        let publisher = input.parse()?;
-        input.parse::<Token![,]>()?;
-
-        input.parse::<Ident>()?;
-        input.parse::<Token![:]>()?;
-        let wrap_type = input.parse()?;
-        let comma = input.parse::<Token![,]>();
-
-        let signal_matcher = if comma.is_ok() && input.peek(Ident) {
-            input.parse::<Ident>()?;
+        let mut publisher = None;
+        let mut wrap_type = None;
+        let mut description = None;
+        let mut capabilities = None;
+        let mut icon = None;
+        let mut homepage = None;
+        let mut supported_mimetypes = None;
+        let mut signal_matcher = None;
+
+        while !input.is_empty() {
+            let key = input.parse::<Ident>()?;
             input.parse::<Token![:]>()?;
-            Some(input.parse()?)
-        } else {
-            None
-        };
+            match key.to_string().as_str() {
+                "publisher" => set_once(&mut publisher, &key, input.parse()?)?,
+                "wrap_type" => set_once(&mut wrap_type, &key, input.parse()?)?,
+                "description" => set_once(&mut description, &key, parse_non_empty(input)?)?,
+                "capabilities" => set_once(&mut capabilities, &key, input.parse()?)?,
+                "icon" => set_once(&mut icon, &key, parse_non_empty(input)?)?,
+                "homepage" => set_once(&mut homepage, &key, parse_homepage(input)?)?,
+                "supported_mimetypes" => {
+                    set_once(&mut supported_mimetypes, &key, parse_mimetypes(input)?)?
+                }
+                "common_query" => set_once(&mut signal_matcher, &key, input.parse()?)?,
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!(
+                            "Unknown key `{}`, expected one of: {}",
+                            key,
+                            WRAP_KEYS.join(", ")
+                        ),
+                    ))
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
 
+        let missing = |key: &str| syn::Error::new(Span::call_site(), format!("Missing `{}`", key));
         Ok(Self {
-            publisher,
-            wrap_type,
+            publisher: publisher.ok_or_else(|| missing("publisher"))?,
+            wrap_type: wrap_type.ok_or_else(|| missing("wrap_type"))?,
+            description,
+            capabilities,
+            icon,
+            homepage,
+            supported_mimetypes: supported_mimetypes.unwrap_or_default(),
             common_query_matcher: signal_matcher,
         })
     }
 }
 
-fn to_snake_case_and_remove_last(s: &str) -> String {
+fn to_snake_case(s: &str) -> String {
     let mut result = String::new();
     let mut last_was_upper = true;
 
@@ -53,11 +160,6 @@ fn to_snake_case_and_remove_last(s: &str) -> String {
         }
     }
 
-    // Remove last item
-    if let Some(last_underscore_index) = result.rfind('_') {
-        result.truncate(last_underscore_index);
-    }
-
     result
 }
 
@@ -66,17 +168,43 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
     let WrapDefinition {
         publisher,
         wrap_type,
+        description,
+        capabilities,
+        icon,
+        homepage,
+        supported_mimetypes,
         common_query_matcher: signal_matcher,
     } = parse_macro_input!(attr as WrapDefinition);
     let item = parse_macro_input!(item as DeriveInput);
 
     let signal_arms = if let Some(signal_matcher) = signal_matcher {
-        let arms = signal_matcher.arms;
-        quote! { #(#arms),* }
+        // Every arm needs a trailing comma, since the default arm follows them
+        let arms = signal_matcher.arms.into_iter().map(|mut arm| {
+            arm.comma = Some(Default::default());
+            arm
+        });
+        quote! { #(#arms)* }
     } else {
         quote! {}
     };
 
+    // Wraps ask for no privileges unless they list some
+    let capabilities = capabilities.map_or_else(|| quote! { vec![] }, |expr| quote! { #expr });
+
+    // Anything not declared is taken from the package, if it has it
+    let description = description.map_or_else(
+        || quote! { env!("CARGO_PKG_DESCRIPTION") },
+        |description| quote! { #description },
+    );
+    let homepage = homepage.map_or_else(
+        || quote! { env!("CARGO_PKG_HOMEPAGE") },
+        |homepage| quote! { #homepage },
+    );
+    let icon = icon.map_or_else(|| quote! { None }, |icon| quote! { Some(#icon.into()) });
+    let supported_mimetypes = supported_mimetypes
+        .iter()
+        .map(|(type_, subtype)| quote! { MimeType::new(#type_, #subtype) });
+
     // Name of the struct use to declare and impl the wrap
     let struct_name = &item.ident;
 
@@ -88,25 +216,28 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
                 .iter()
                 .map(|f| &f.ty)
                 .collect::<Vec<_>>(),
-            _ => panic!("Expected a tuple struct"),
+            _ => {
+                return syn::Error::new(struct_name.span(), "Expected a tuple struct of queries")
+                    .to_compile_error()
+                    .into()
+            }
         },
-        _ => panic!("Expected a struct"),
+        _ => {
+            return syn::Error::new(struct_name.span(), "Expected a struct")
+                .to_compile_error()
+                .into()
+        }
     };
 
+    // Each interface's trait handles its own query, see HandleSignal
     let match_query_arms = supported_queries.iter().map(|query| {
-        let tokens = quote! { #query };
-        let query_string = tokens.to_string();
-        let mut query_fn_name = to_snake_case_and_remove_last(&query_string);
-        query_fn_name.push_str("_query");
-        let function_name = Ident::new(&query_fn_name, proc_macro2::Span::call_site());
-        
         quote! {
             #query::QUERY_ID => {
                 bincode::decode_from_slice::<#query, _>(input_signal_slice, config)
                     .map_err(|error| WrapError::DecodeFailed(format!("{}", error)))
                     .and_then(|(input_signal, _)| {
-                        let response = #struct_name::#function_name(input_signal);
-    
+                        let response = <#struct_name as HandleSignal<#query>>::handle_signal(input_signal);
+
                         bincode::encode_to_vec(response, config)
                             .map_err(|error| WrapError::EncodeFailed(format!("{}", error)))
                     })
@@ -119,21 +250,13 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
 
         pub const WRAP_NAME: &str = env!("CARGO_CRATE_NAME");
         pub const WRAP_VERSION: &str = env!("CARGO_PKG_VERSION");
-        pub const WRAP_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+        pub const WRAP_DESCRIPTION: &str = #description;
+        const WRAP_HOMEPAGE: &str = #homepage;
 
-        #[no_mangle]
-        pub extern "C" fn signal(
-            interface_id: u64,
-            input_signal_ptr: u64,
-            input_signal_length: u64,
-        ) -> u64 {
+        /// Decode a signal, hand it to the wrap and encode its response
+        fn dispatch_signal(interface_id: u64, input_signal_slice: &[u8]) -> Vec<u8> {
             let config = bincode::config::standard();
         
-            // Parse input object
-            let input_signal_slice = unsafe {
-                std::slice::from_raw_parts(input_signal_ptr as *const u8, input_signal_length as usize)
-            };
-        
             // Produce a response response
             let output_signal_slice_result = match interface_id {
                 #( #match_query_arms )*
@@ -142,18 +265,54 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
             };
         
             // An error might stille need to be serialized
-            let output_signal_slice = match output_signal_slice_result {
+            match output_signal_slice_result {
                 Ok(output_signal_slice) => output_signal_slice,
                 error => bincode::encode_to_vec(&error, config).expect("Could not encode error"),
+            }
+        }
+
+        // Exports are only generated for wasm, so several wraps can be linked into the host natively
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn signal(
+            interface_id: u64,
+            input_signal_ptr: u64,
+            input_signal_length: u64,
+        ) -> u64 {
+            // Panics are reported to the host, since the trap that follows doesn't say much
+            host::install_panic_hook();
+
+            // Parse input object
+            let input_signal_slice = unsafe {
+                std::slice::from_raw_parts(input_signal_ptr as *const u8, input_signal_length as usize)
             };
+            let output_signal_slice = dispatch_signal(interface_id, input_signal_slice);
         
-            // Compact both length and pointer into a single u64
-            let len = (output_signal_slice.len() as u64) & 0xFFFFFFFF;
-            let ptr = (output_signal_slice.as_ptr() as u64) << 32;
-        
-            std::mem::forget(output_signal_slice);
-        
-            len | ptr
+            // The host frees the output buffer with dealloc once it is done decoding it
+            memory::into_raw_buffer(output_signal_slice)
+        }
+
+        /// Allocate a buffer for the host to write an input signal into
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub extern "C" fn alloc(len: u64) -> u64 {
+            memory::alloc(len)
+        }
+
+        /// Free a buffer once the host is done with it
+        #[cfg(target_arch = "wasm32")]
+        #[no_mangle]
+        pub unsafe extern "C" fn dealloc(ptr: u64, len: u64) {
+            memory::dealloc(ptr, len)
+        }
+
+        /// The wrap's signal handler, for the host to link it natively rather than load it as wasm
+        #[cfg(not(target_arch = "wasm32"))]
+        pub fn native_wrap() -> native::NativeWrap {
+            native::NativeWrap {
+                name: WRAP_NAME,
+                signal: dispatch_signal,
+            }
         }
 
         impl #struct_name {
@@ -165,6 +324,11 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
                     description: WRAP_DESCRIPTION.into(),
                     publisher: #publisher,
                     interface_version: InterfaceVersion::new(),
+                    capabilities: #capabilities,
+                    icon: #icon,
+                    homepage: (!WRAP_HOMEPAGE.is_empty()).then(|| WRAP_HOMEPAGE.into()),
+                    supported_mimetypes: vec![ #( #supported_mimetypes ),* ],
+                    verified_publisher: None,
                 })
             }
         }
@@ -173,6 +337,9 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
             fn common_query(query: CommonQuery) -> CommonResult {
                 match query {
                     CommonQuery::AskMetadata => Ok(#struct_name::metadata()),
+                    CommonQuery::AskSupportedQueries => Ok(CommonResponse::SupportedQueries(vec![
+                        #( SupportedQuery::of::<#supported_queries>() ),*
+                    ])),
                     #signal_arms
                     _ => Err(WrapError::UnsupportedSignal),
                 }
@@ -181,3 +348,378 @@ pub fn define_wrap(attr: TokenStream, item: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+/// Generate a typed client for a query, with one method per variant, and register its `QUERY_ID`
+///
+/// `MimetypeQuery` gets a `MimetypeClient`, so hosts write `client.ask_parse(data)` rather than
+/// building `MimetypeQuery::AskParse { data }` by hand. Registering the id fails to compile when
+/// another query already uses it, which is why queries can only be derived inside of `hmny_common`
+#[proc_macro_derive(HarmonyClient)]
+pub fn derive_harmony_client(item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as DeriveInput);
+    let query = &item.ident;
+    let vis = &item.vis;
+
+    let syn::Data::Enum(data_enum) = &item.data else {
+        return syn::Error::new(query.span(), "HarmonyClient can only be derived for enums")
+            .to_compile_error()
+            .into();
+    };
+
+    let query_name = query.to_string();
+    let interface_name = query_name.strip_suffix("Query").unwrap_or(&query_name);
+    let client = Ident::new(&format!("{}Client", interface_name), query.span());
+
+    let methods = data_enum.variants.iter().map(|variant| {
+        let variant_name = &variant.ident;
+        let method = Ident::new(
+            &to_snake_case(&variant_name.to_string()),
+            variant_name.span(),
+        );
+
+        let (args, construct) = match &variant.fields {
+            Fields::Named(fields) => {
+                let names: Vec<_> = fields.named.iter().map(|f| &f.ident).collect();
+                let types = fields.named.iter().map(|f| &f.ty);
+                (
+                    quote! { #( #names: #types ),* },
+                    quote! { #query::#variant_name { #( #names ),* } },
+                )
+            }
+            Fields::Unnamed(fields) => {
+                let names: Vec<_> = (0..fields.unnamed.len())
+                    .map(|i| Ident::new(&format!("arg{}", i), Span::call_site()))
+                    .collect();
+                let types = fields.unnamed.iter().map(|f| &f.ty);
+                (
+                    quote! { #( #names: #types ),* },
+                    quote! { #query::#variant_name( #( #names ),* ) },
+                )
+            }
+            Fields::Unit => (quote! {}, quote! { #query::#variant_name }),
+        };
+
+        let doc = format!("Send [`{}::{}`]", query_name, variant_name);
+        quote! {
+            #[doc = #doc]
+            pub fn #method(
+                &mut self,
+                #args
+            ) -> Result<<#query as crate::interface::HarmonySignal>::ResponseType, Sender::Error> {
+                self.sender.send(#construct)
+            }
+        }
+    });
+
+    let client_doc = format!("Typed client for [`{}`]", query_name);
+    quote! {
+        #[doc = #client_doc]
+        #vis struct #client<Sender> {
+            sender: Sender,
+        }
+
+        impl<Sender: crate::interface::SignalSender> #client<Sender> {
+            pub fn new(sender: Sender) -> Self {
+                Self { sender }
+            }
+
+            #( #methods )*
+        }
+
+        // Two queries sharing an id would implement the same trait twice
+        impl crate::interface::QueryId<{ <#query as crate::interface::HarmonySignal>::QUERY_ID }>
+            for crate::interface::QueryRegistry
+        {
+        }
+    }
+    .into()
+}
+
+fn to_pascal_case(s: &str) -> String {
+    s.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// 64-bit FNV-1a, so query ids never change between builds or compiler versions
+fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Find the `Query` or `Response` enum of an interface declaration
+fn take_enum(items: &mut Vec<syn::Item>, name: &str, module: &Ident) -> syn::Result<syn::ItemEnum> {
+    let position = items
+        .iter()
+        .position(|item| matches!(item, syn::Item::Enum(item) if item.ident == name));
+    match position.map(|position| items.remove(position)) {
+        Some(syn::Item::Enum(item)) => Ok(item),
+        _ => Err(syn::Error::new(
+            module.span(),
+            format!("Missing the interface's `{}` enum", name),
+        )),
+    }
+}
+
+/// Open the interface's client on anything routing signals, through the wraps of the key returned
+/// by `key`
+fn client_accessor(
+    key: &syn::ExprClosure,
+    vis: &syn::Visibility,
+    name: &str,
+    client_name: &Ident,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let params = key
+        .inputs
+        .iter()
+        .map(|param| match param {
+            syn::Pat::Type(param) => Ok(param),
+            _ => Err(syn::Error::new_spanned(param, "Key parameters need a type")),
+        })
+        .collect::<syn::Result<Vec<_>>>()?;
+    let body = &key.body;
+    let trait_name = Ident::new(&format!("{}Clients", name), client_name.span());
+    let method_name = Ident::new(&to_snake_case(name), client_name.span());
+    let doc = format!(
+        "Open a [`{}`] on anything routing signals to wraps",
+        client_name
+    );
+
+    Ok(quote! {
+        #[doc = #doc]
+        #vis trait #trait_name: crate::interface::SignalRouter {
+            fn #method_name(&mut self, #( #params ),*) -> #client_name<Self::Sender<'_>> {
+                #client_name::new(self.sender(#body))
+            }
+        }
+
+        impl<Router: crate::interface::SignalRouter + ?Sized> #trait_name for Router {}
+    })
+}
+
+fn harmony_interface_impl(
+    attr: proc_macro2::TokenStream,
+    item: proc_macro2::TokenStream,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let mut version = None;
+    let mut key = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("version") {
+            let value: syn::LitInt = meta.value()?.parse()?;
+            version = Some(value.base10_parse::<u32>()?);
+            Ok(())
+        } else if meta.path.is_ident("key") {
+            key = Some(meta.value()?.parse::<syn::ExprClosure>()?);
+            Ok(())
+        } else {
+            Err(meta.error("Unknown key, expected `version` or `key`"))
+        }
+    });
+    syn::parse::Parser::parse2(parser, attr)?;
+    let version = version.ok_or_else(|| syn::Error::new(Span::call_site(), "Missing `version`"))?;
+
+    let module: syn::ItemMod = syn::parse2(item)?;
+    let Some((_, mut items)) = module.content else {
+        return Err(syn::Error::new(
+            module.ident.span(),
+            "Interfaces must be declared in an inline module",
+        ));
+    };
+    let mut query = take_enum(&mut items, "Query", &module.ident)?;
+    let mut response = take_enum(&mut items, "Response", &module.ident)?;
+    if let Some(item) = items.first() {
+        return Err(syn::Error::new_spanned(
+            item,
+            "Interfaces only declare a `Query` and a `Response` enum",
+        ));
+    }
+
+    let name = to_pascal_case(&module.ident.to_string());
+    let vis = &query.vis;
+    query.ident = Ident::new(&format!("{}Query", name), query.ident.span());
+    response.ident = Ident::new(&format!("{}Response", name), response.ident.span());
+    let query_name = &query.ident;
+    let response_name = &response.ident;
+    let result_name = Ident::new(&format!("{}Result", name), module.ident.span());
+    let client_name = Ident::new(&format!("{}Client", name), module.ident.span());
+    let accessor = match &key {
+        Some(key) => client_accessor(key, vis, &name, &client_name)?,
+        None => quote! {},
+    };
+    let trait_name = Ident::new(&format!("{}Interface", name), module.ident.span());
+    let method_name = Ident::new(
+        &format!("{}_query", to_snake_case(&name)),
+        module.ident.span(),
+    );
+
+    // Changes to the version change the id too, so older wraps never receive queries they can't decode
+    let query_id = stable_hash(&format!("{}@{}", name, version));
+
+    Ok(quote! {
+        #[derive(Clone, ::bincode::Decode, ::bincode::Encode, PartialEq, Debug, ::hmny_macros::HarmonyClient)]
+        #query
+
+        #[derive(Clone, ::bincode::Decode, ::bincode::Encode, PartialEq, Debug)]
+        #response
+
+        #vis type #result_name = Result<#response_name, crate::interface::WrapError>;
+
+        /// Implemented by wraps handling this interface, and listed in `define_wrap`
+        #vis trait #trait_name {
+            fn #method_name(query: #query_name) -> #result_name;
+        }
+
+        impl<Wrap: #trait_name> crate::interface::HandleSignal<#query_name> for Wrap {
+            fn handle_signal(query: #query_name) -> #result_name {
+                Wrap::#method_name(query)
+            }
+        }
+
+        impl crate::interface::HarmonySignal for #query_name {
+            type ResponseType = #response_name;
+            const QUERY_ID: u64 = #query_id;
+            const VERSION: u32 = #version;
+        }
+
+        #accessor
+    })
+}
+
+/// Declare an interface of `hmny_common` from its `Query` and `Response` enums
+///
+/// ```ignore
+/// #[harmony_interface(version = 1, key = |mime_type: MimeType| WrapKey::Mimetype(mime_type))]
+/// mod mimetype {
+///     pub enum Query {
+///         AskParse { data: DataType },
+///     }
+///
+///     pub enum Response {
+///         Dimension(Dimension),
+///     }
+/// }
+/// ```
+///
+/// The module is replaced by `MimetypeQuery`, `MimetypeResponse`, `MimetypeResult`, the
+/// `MimetypeInterface` trait wraps implement and a typed `MimetypeClient`. The query id is derived
+/// from the interface's name and version, and bumping the version is the only way to change it.
+///
+/// With a `key`, hosts open the client with `wraps.mimetype(mime_type)`, sending signals to the
+/// wraps of the key it returns
+#[proc_macro_attribute]
+pub fn harmony_interface(attr: TokenStream, item: TokenStream) -> TokenStream {
+    harmony_interface_impl(attr.into(), item.into())
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(attr: &str, item: &str) -> syn::Result<String> {
+        harmony_interface_impl(attr.parse().unwrap(), item.parse().unwrap())
+            .map(|tokens| tokens.to_string())
+    }
+
+    fn interface_error(attr: &str, item: &str) -> String {
+        interface(attr, item).unwrap_err().to_string()
+    }
+
+    fn wrap_error(definition: &str) -> String {
+        match syn::parse_str::<WrapDefinition>(definition) {
+            Ok(_) => panic!("Expected {:?} to be rejected", definition),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    const INTERFACE: &str =
+        "mod mimetype { pub enum Query { AskParse } pub enum Response { Parsed } }";
+
+    #[test]
+    fn test_stable_hash() {
+        // Reference values of 64-bit FNV-1a
+        assert_eq!(stable_hash(""), 0xcbf29ce484222325);
+        assert_eq!(stable_hash("a"), 0xaf63dc4c8601ec8c);
+        assert_ne!(stable_hash("Mimetype@1"), stable_hash("Mimetype@2"));
+    }
+
+    #[test]
+    fn test_to_pascal_case() {
+        assert_eq!(to_pascal_case("mimetype"), "Mimetype");
+        assert_eq!(to_pascal_case("home_screen"), "HomeScreen");
+        assert_eq!(
+            to_pascal_case("_trailing__underscores_"),
+            "TrailingUnderscores"
+        );
+        assert_eq!(to_pascal_case(""), "");
+    }
+
+    #[test]
+    fn test_interface() {
+        let tokens = interface("version = 1", INTERFACE).unwrap();
+        assert!(tokens.contains("MimetypeQuery"));
+        assert!(tokens.contains(&stable_hash("Mimetype@1").to_string()));
+        assert!(!tokens.contains("MimetypeClients"));
+
+        let tokens = interface(
+            "version = 1, key = |mime_type: MimeType| WrapKey::Mimetype(mime_type)",
+            INTERFACE,
+        )
+        .unwrap();
+        assert!(tokens.contains("trait MimetypeClients"));
+    }
+
+    #[test]
+    fn test_interface_errors() {
+        assert_eq!(interface_error("", INTERFACE), "Missing `version`");
+        assert!(interface_error("version = 1, name = 2", INTERFACE).starts_with("Unknown key"));
+        assert!(interface_error("version = 1", "mod mimetype;").contains("inline module"));
+        assert_eq!(
+            interface_error(
+                "version = 1",
+                "mod mimetype { pub enum Query { AskParse } }"
+            ),
+            "Missing the interface's `Response` enum"
+        );
+        assert!(interface_error(
+            "version = 1",
+            "mod mimetype { pub enum Query {} pub enum Response {} struct Extra; }"
+        )
+        .starts_with("Interfaces only declare"));
+        assert_eq!(
+            interface_error("version = 1, key = |mime_type| mime_type", INTERFACE),
+            "Key parameters need a type"
+        );
+    }
+
+    #[test]
+    fn test_wrap_definition_errors() {
+        assert!(wrap_error("publisher: a, wrap_type: b, name: c").starts_with("Unknown key `name`"));
+        assert_eq!(
+            wrap_error("publisher: a, publisher: a, wrap_type: b"),
+            "`publisher` is set more than once"
+        );
+        assert_eq!(wrap_error("publisher: a"), "Missing `wrap_type`");
+        assert_eq!(
+            wrap_error(r#"publisher: a, wrap_type: b, description: " ""#),
+            "Must not be empty"
+        );
+        assert!(
+            wrap_error(r#"publisher: a, wrap_type: b, homepage: "ftp://a""#)
+                .contains("http or https")
+        );
+        assert!(
+            wrap_error(r#"publisher: a, wrap_type: b, supported_mimetypes: ["text"]"#)
+                .starts_with("Expected a mimetype")
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! Test wraps end-to-end, through the same signal ABI and host functions Harmony uses.
+//!
+//! Wraps are loaded headlessly, without any Bevy app or window:
+//!
+//! ```no_run
+//! use hmny_common::prelude::*;
+//! use hmny_wrap_test::WrapTester;
+//!
+//! let Some(mut wrap) = WrapTester::build_or_skip("mimetype_markdown") else {
+//!     return;
+//! };
+//! wrap.assert_parse_snapshot("heading", DataType::String("# Hello".into()));
+//! ```
+//!
+//! Building wraps needs the `wasm32-unknown-unknown` target, so tests are skipped without it.
+
+use hmny::wrap::{LoadedWrap, ModuleCache, SignalError, WrapLimits, WrapStorage};
+use hmny_common::prelude::*;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs, io};
+
+const WASM_TARGET: &str = "wasm32-unknown-unknown";
+/// Set to rewrite snapshots that no longer match instead of failing
+const UPDATE_SNAPSHOTS_VAR: &str = "HMNY_UPDATE_SNAPSHOTS";
+const SNAPSHOT_DIR: &str = "tests/snapshots";
+const SNAPSHOT_EXTENSION: &str = "snap";
+
+#[derive(Debug)]
+pub enum TestError {
+    Io(io::Error),
+    BuildFailed(String),
+    /// Install it with `rustup target add wasm32-unknown-unknown`
+    TargetNotInstalled,
+    LoadFailed(hmny::wrap::WrapLoaderError),
+}
+
+impl From<io::Error> for TestError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// A single wrap loaded for testing
+///
+/// Every capability the wrap asks for is granted, and its storage is kept in memory
+pub struct WrapTester {
+    wrap: LoadedWrap,
+}
+
+fn workspace_dir() -> Result<PathBuf, TestError> {
+    let output = Command::new(env::var("CARGO").unwrap_or("cargo".into()))
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .output()?;
+    if !output.status.success() {
+        return Err(TestError::BuildFailed(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+
+    let manifest = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Ok(manifest.parent().unwrap_or(Path::new(".")).to_path_buf())
+}
+
+/// Whether the standard library of the wasm target is installed, which building wraps needs
+pub fn wasm_target_installed() -> bool {
+    Command::new(env::var("RUSTC").unwrap_or("rustc".into()))
+        .args(["--print", "sysroot"])
+        .output()
+        .is_ok_and(|output| {
+            let sysroot = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+            output.status.success() && sysroot.join("lib/rustlib").join(WASM_TARGET).is_dir()
+        })
+}
+
+impl WrapTester {
+    /// Load a compiled wrap module
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, TestError> {
+        let bytes = fs::read(path)?;
+        Self::from_bytes(bytes)
+    }
+
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self, TestError> {
+        let limits = WrapLimits::default();
+        let quota = limits.storage_quota;
+        let mut wrap = LoadedWrap::from_bytes(bytes, limits, &ModuleCache::disabled())
+            .map_err(TestError::LoadFailed)?;
+
+        let capabilities = wrap.get_metadata().capabilities.iter().copied().collect();
+        let env = wrap.host_env_mut();
+        env.capabilities = capabilities;
+        env.storage = WrapStorage::in_memory(quota);
+
+        Ok(Self { wrap })
+    }
+
+    /// Test a wrap linked natively, to step through it with a debugger
+    ///
+    /// Storage and the other host functions use their std fallbacks, shared by every native wrap
+    #[cfg(feature = "native")]
+    pub fn native(wrap: hmny_common::native::NativeWrap) -> Result<Self, TestError> {
+        let wrap =
+            LoadedWrap::from_native(wrap, WrapLimits::default()).map_err(TestError::LoadFailed)?;
+        Ok(Self { wrap })
+    }
+
+    /// Build a wrap package of the workspace for wasm, then load it
+    pub fn build(package: &str) -> Result<Self, TestError> {
+        if !wasm_target_installed() {
+            return Err(TestError::TargetNotInstalled);
+        }
+
+        let workspace_dir = workspace_dir()?;
+        let output = Command::new(env::var("CARGO").unwrap_or("cargo".into()))
+            .current_dir(&workspace_dir)
+            .args(["build", "--release", "--target", WASM_TARGET, "-p", package])
+            .output()?;
+        if !output.status.success() {
+            return Err(TestError::BuildFailed(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        let target_dir = env::var_os("CARGO_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| workspace_dir.join("target"));
+        let file_name = format!("{}.wasm", package.replace('-', "_"));
+        Self::load(target_dir.join(WASM_TARGET).join("release").join(file_name))
+    }
+
+    /// Build a wrap like [`WrapTester::build`], or return None so the test can be skipped when the
+    /// wasm target isn't installed
+    pub fn build_or_skip(package: &str) -> Option<Self> {
+        match Self::build(package) {
+            Ok(wrap) => Some(wrap),
+            Err(TestError::TargetNotInstalled) => {
+                eprintln!(
+                    "Skipping test of {}, the {} target isn't installed",
+                    package, WASM_TARGET
+                );
+                None
+            }
+            Err(error) => panic!("Could not build {}: {:?}", package, error),
+        }
+    }
+
+    pub fn metadata(&self) -> &WrapMetdata {
+        self.wrap.get_metadata()
+    }
+
+    /// The loaded wrap, to test anything the tester doesn't cover
+    pub fn wrap_mut(&mut self) -> &mut LoadedWrap {
+        &mut self.wrap
+    }
+
+    pub fn signal<Signal: HarmonySignal>(
+        &mut self,
+        signal: Signal,
+    ) -> Result<Signal::ResponseType, SignalError> {
+        self.wrap.send_signal_with_policy(signal)
+    }
+
+    #[track_caller]
+    pub fn assert_response<Signal: HarmonySignal>(
+        &mut self,
+        signal: Signal,
+        expected: Signal::ResponseType,
+    ) where
+        Signal::ResponseType: PartialEq + Debug,
+    {
+        match self.signal(signal) {
+            Ok(response) => assert_eq!(response, expected),
+            Err(error) => panic!("Wrap failed to respond: {:?}", error),
+        }
+    }
+
+    /// Ask the wrap to parse data, panicking if it can't
+    #[track_caller]
+    pub fn parse(&mut self, data: DataType) -> Dimension {
+        match self.signal(MimetypeQuery::AskParse { data }) {
+            Ok(MimetypeResponse::Dimension(dimension)) => dimension,
+            Err(error) => panic!("Wrap failed to parse: {:?}", error),
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_parses_to(&mut self, data: DataType, expected: Dimension) {
+        assert_eq!(self.parse(data), expected);
+    }
+
+    /// Compare the response to a golden file in `tests/snapshots`
+    ///
+    /// Missing snapshots are written, and set `HMNY_UPDATE_SNAPSHOTS=1` to rewrite the others
+    #[track_caller]
+    pub fn assert_snapshot<Signal: HarmonySignal>(&mut self, name: &str, signal: Signal)
+    where
+        Signal::ResponseType: Debug,
+    {
+        match self.signal(signal) {
+            Ok(response) => assert_snapshot(name, &response),
+            Err(error) => panic!("Wrap failed to respond: {:?}", error),
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_parse_snapshot(&mut self, name: &str, data: DataType) {
+        let dimension = self.parse(data);
+        assert_snapshot(name, &dimension);
+    }
+}
+
+/// Compare a value's pretty debug output to a golden file in `tests/snapshots`
+#[track_caller]
+pub fn assert_snapshot(name: &str, value: &impl Debug) {
+    let manifest_dir = env::var_os("CARGO_MANIFEST_DIR").unwrap_or(".".into());
+    let path = Path::new(&manifest_dir)
+        .join(SNAPSHOT_DIR)
+        .join(name)
+        .with_extension(SNAPSHOT_EXTENSION);
+    let actual = format!("{:#?}\n", value);
+
+    let update = env::var_os(UPDATE_SNAPSHOTS_VAR).is_some_and(|value| value != "0");
+    match fs::read_to_string(&path) {
+        Ok(expected) if expected == actual => {}
+        Ok(expected) if !update => {
+            panic!(
+                "Snapshot {:?} doesn't match, set {}=1 to update it\n--- expected\n{}\n--- actual\n{}",
+                path, UPDATE_SNAPSHOTS_VAR, expected, actual
+            );
+        }
+        _ => {
+            let write = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&path, &actual));
+            if let Err(error) = write {
+                panic!("Could not write snapshot {:?}: {:?}", path, error);
+            }
+        }
+    }
+}
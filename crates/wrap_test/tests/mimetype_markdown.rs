@@ -0,0 +1,32 @@
+use hmny::wrap::SignalError;
+use hmny_common::prelude::*;
+use hmny_wrap_test::WrapTester;
+
+const MARKDOWN: &str = "# Hello\n\nSome *markdown*";
+
+#[test]
+fn test_parse() {
+    let Some(mut wrap) = WrapTester::build_or_skip("mimetype_markdown") else {
+        return;
+    };
+    let dimension = wrap.parse(DataType::String(MARKDOWN.into()));
+    assert_eq!(dimension.title, "Hello");
+    assert!(!dimension.children.is_empty());
+
+    // Markdown sent as bytes parses the same as strings
+    wrap.assert_parses_to(DataType::Bytes(MARKDOWN.into()), dimension);
+}
+
+#[test]
+fn test_parse_invalid_utf8() {
+    let Some(mut wrap) = WrapTester::build_or_skip("mimetype_markdown") else {
+        return;
+    };
+    let result = wrap.signal(MimetypeQuery::AskParse {
+        data: DataType::Bytes(vec![0xff, 0xfe]),
+    });
+    assert!(matches!(
+        result,
+        Err(SignalError::WrapError(WrapError::Other(message))) if message == "Markdown must be valid utf-8"
+    ));
+}
@@ -0,0 +1,51 @@
+use hmny_common::prelude::*;
+use hmny_wrap_test::WrapTester;
+
+#[test]
+fn test_ping() {
+    let Some(mut wrap) = WrapTester::build_or_skip("test_wrap") else {
+        return;
+    };
+    assert_eq!(wrap.metadata().name, "test_wrap");
+    wrap.assert_snapshot(
+        "ping",
+        CommonQuery::Ping {
+            message: "tester".into(),
+        },
+    );
+}
+
+#[test]
+fn test_snapshot_and_restore() {
+    let Some(mut wrap) = WrapTester::build_or_skip("test_wrap") else {
+        return;
+    };
+    wrap.assert_response(
+        CommonQuery::Restore {
+            state: 41u64.to_le_bytes().to_vec(),
+        },
+        CommonResponse::Restored,
+    );
+    wrap.signal(CommonQuery::Ping {
+        message: "tester".into(),
+    })
+    .unwrap();
+    wrap.assert_response(
+        CommonQuery::Snapshot,
+        CommonResponse::Snapshot {
+            state: 42u64.to_le_bytes().to_vec(),
+        },
+    );
+}
+
+#[cfg(feature = "native")]
+#[test]
+fn test_native_ping() {
+    let mut wrap = WrapTester::native(test_wrap::native_wrap()).unwrap();
+    wrap.assert_snapshot(
+        "ping",
+        CommonQuery::Ping {
+            message: "tester".into(),
+        },
+    );
+}
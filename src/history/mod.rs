@@ -1,43 +1,144 @@
 use crate::canvas;
 use crate::canvas::layout;
-use crate::wrap::{WrapKey, Wraps};
-use bevy::prelude::*;
+use crate::wrap::{RequestId, SignalRequest, SignalResponse, WrapReplaced};
+use bevy::{prelude::*, utils::HashMap};
 use hmny_common::prelude::*;
 
 pub struct HistoryPlugin;
 
 impl Plugin for HistoryPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup);
+        app.init_resource::<PendingDimensions>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (on_home_screen, on_dimension, on_wrap_replaced).chain(),
+            );
     }
 }
 
-fn setup(mut wraps: ResMut<Wraps>, mut commands: Commands, mut images: ResMut<Assets<Image>>) {
-    match wraps.signal(WrapKey::HomeScreen, HomescreenQuery::AskHomeScreen) {
-        Ok(HomescreenResponse::HomeScreen { mime_type, data }) => {
-            info!(
-                r#"Load home screen with mimetype: "{}" data: "{:?}""#,
-                mime_type, data
-            );
+/// A dimension on screen, along with the data it was parsed from so it can be parsed again
+///
+/// Streams are closed once the first parse completes, so only inline and referenced data can be parsed again
+#[derive(Component)]
+pub struct DisplayedDimension {
+    pub key: WrapKey,
+    pub wrap_name: String,
+    pub data: DataType,
+}
 
-            match wraps.signal(
-                WrapKey::Mimetype(mime_type),
-                MimetypeQuery::AskParse { data },
-            ) {
-                Ok(MimetypeResponse::Dimension(dimension)) => {
-                    info!(r#"Loading dimension: "{:?}""#, dimension);
-                    let dimension_entity = commands.spawn(SpatialBundle::default()).id();
-                    for element in dimension.children.into_iter() {
-                        summon_element(element, dimension_entity, &mut commands, &mut images);
-                    }
+/// Data sent to be parsed, waiting for its dimension
+#[derive(Resource, Default)]
+struct PendingDimensions {
+    data: HashMap<RequestId, DataType>,
+}
+
+fn request_dimension(
+    key: WrapKey,
+    data: DataType,
+    pending: &mut PendingDimensions,
+    requests: &mut EventWriter<SignalRequest<MimetypeQuery>>,
+) {
+    let request = SignalRequest::new(key, MimetypeQuery::AskParse { data: data.clone() });
+    pending.data.insert(request.id, data);
+    requests.send(request);
+}
+
+fn setup(mut requests: EventWriter<SignalRequest<HomescreenQuery>>) {
+    requests.send(SignalRequest::new(
+        WrapKey::HomeScreen,
+        HomescreenQuery::AskHomeScreen,
+    ));
+}
+
+fn on_home_screen(
+    mut responses: EventReader<SignalResponse<HomescreenQuery>>,
+    mut pending: ResMut<PendingDimensions>,
+    mut requests: EventWriter<SignalRequest<MimetypeQuery>>,
+) {
+    for SignalResponse { result, .. } in responses.read() {
+        match result {
+            Ok(HomescreenResponse::HomeScreen { mime_type, data }) => {
+                info!(
+                    r#"Load home screen with mimetype: "{}" data: "{:?}""#,
+                    mime_type, data
+                );
+
+                request_dimension(
+                    WrapKey::Mimetype(mime_type.clone()),
+                    data.clone(),
+                    &mut pending,
+                    &mut requests,
+                );
+            }
+            other => {
+                error!("Could not load home screen data: {:?}", other);
+            }
+        }
+    }
+}
+
+fn on_dimension(
+    mut responses: EventReader<SignalResponse<MimetypeQuery>>,
+    mut pending: ResMut<PendingDimensions>,
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for SignalResponse {
+        id,
+        key,
+        wrap_name,
+        result,
+    } in responses.read()
+    {
+        let data = pending.data.remove(id);
+        match result {
+            Ok(MimetypeResponse::Dimension(dimension)) => {
+                info!(r#"Loading dimension: "{:?}""#, dimension);
+                let mut dimension_entity = commands.spawn(SpatialBundle::default());
+                if let (Some(wrap_name), Some(data)) = (wrap_name, data) {
+                    dimension_entity.insert(DisplayedDimension {
+                        key: key.clone(),
+                        wrap_name: wrap_name.clone(),
+                        data,
+                    });
                 }
-                other => {
-                    error!("Could not load dimension: {:?}", other);
+                let dimension_entity = dimension_entity.id();
+                for element in dimension.children.iter().cloned() {
+                    summon_element(element, dimension_entity, &mut commands, &mut images);
                 }
             }
+            other => {
+                error!("Could not load dimension: {:?}", other);
+            }
         }
-        other => {
-            error!("Could not load home screen data: {:?}", other);
+    }
+}
+
+/// Parse dimensions produced by a wrap again once it has been rebuilt
+fn on_wrap_replaced(
+    mut replaced: EventReader<WrapReplaced>,
+    dimensions: Query<(Entity, &DisplayedDimension)>,
+    mut pending: ResMut<PendingDimensions>,
+    mut requests: EventWriter<SignalRequest<MimetypeQuery>>,
+    mut commands: Commands,
+) {
+    for WrapReplaced { previous, .. } in replaced.read() {
+        let name = &previous.name;
+        for (entity, dimension) in dimensions.iter() {
+            if dimension.wrap_name == *name {
+                info!(
+                    "Parsing {:?} again after {:?} was reloaded",
+                    dimension.key, name
+                );
+                commands.entity(entity).despawn_recursive();
+                request_dimension(
+                    dimension.key.clone(),
+                    dimension.data.clone(),
+                    &mut pending,
+                    &mut requests,
+                );
+            }
         }
     }
 }
@@ -5,7 +5,7 @@ use hmny_common::*;
 mod canvas;
 mod dimension;
 mod history;
-mod wrap;
+pub mod wrap;
 
 pub struct HarmonyPlugin;
 
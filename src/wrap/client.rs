@@ -0,0 +1,60 @@
+use super::{SignalError, Wraps};
+use hmny_common::prelude::*;
+
+/// Sends signals to the wraps of a key, blocking until one of them responds
+///
+/// Usually wrapped in one of the typed clients, such as `wraps.mimetype(mime_type).ask_parse(data)`
+pub struct WrapClient<'a> {
+    wraps: &'a mut Wraps,
+    key: WrapKey,
+}
+
+impl SignalSender for WrapClient<'_> {
+    type Error = SignalError;
+
+    fn send<Signal: HarmonySignal>(
+        &mut self,
+        signal: Signal,
+    ) -> Result<Signal::ResponseType, SignalError> {
+        self.wraps.signal(self.key.clone(), signal)
+    }
+}
+
+impl Wraps {
+    pub fn client(&mut self, key: WrapKey) -> WrapClient<'_> {
+        WrapClient { wraps: self, key }
+    }
+}
+
+/// Opens the typed client of every interface, such as `wraps.homescreen()`
+impl SignalRouter for Wraps {
+    type Sender<'a> = WrapClient<'a>;
+
+    fn sender(&mut self, key: WrapKey) -> WrapClient<'_> {
+        self.client(key)
+    }
+}
+
+#[cfg(all(test, feature = "native"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_clients() {
+        let mut wraps = Wraps::default();
+        wraps
+            .load_native(homescreen_default::native_wrap())
+            .unwrap();
+        wraps.load_native(mimetype_markdown::native_wrap()).unwrap();
+
+        let Ok(HomescreenResponse::HomeScreen { mime_type, data }) =
+            wraps.homescreen().ask_home_screen()
+        else {
+            panic!("No home screen");
+        };
+        assert!(matches!(
+            wraps.mimetype(mime_type).ask_parse(data),
+            Ok(MimetypeResponse::Dimension(_))
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+use super::user_data_dir;
+use bevy::prelude::*;
+use hmny_common::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use url::Url;
+
+const CONSENT_FILE: &str = "consent.json";
+
+#[derive(Debug)]
+pub enum ConsentError {
+    Io(std::io::Error),
+    InvalidFile(serde_json::Error),
+    /// Capabilities can only be granted to wraps that asked for them
+    NotRequested(Capability),
+    WrapNotLoaded(String),
+}
+
+impl From<std::io::Error> for ConsentError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Decisions by publisher or source, then by `<wrap name>@<version>`, then by capability name
+type Decisions = BTreeMap<String, BTreeMap<String, BTreeMap<String, bool>>>;
+
+/// Remembers which capabilities the user granted or denied to each version of a wrap
+///
+/// Decisions are tied to the verified publisher, or to where unverified wraps were loaded from, so a
+/// wrap claiming to be someone else doesn't inherit them. They are saved to the user data directory
+/// as soon as they are made
+pub struct ConsentStore {
+    path: Option<PathBuf>,
+    decisions: Decisions,
+}
+
+impl Default for ConsentStore {
+    fn default() -> Self {
+        let path = user_data_dir().map(|dir| dir.join(CONSENT_FILE));
+        Self::new(path.clone()).unwrap_or_else(|error| {
+            warn!("Could not read consent store {:?}: {:?}", path, error);
+            Self {
+                path: None,
+                decisions: Decisions::default(),
+            }
+        })
+    }
+}
+
+/// Only verified publishers are trusted to be who they say they are
+pub(super) fn publisher_key(metadata: &WrapMetdata) -> String {
+    match &metadata.verified_publisher {
+        Some(publisher) => publisher.name().into(),
+        None => format!("{} (unverified)", metadata.publisher.name()),
+    }
+}
+
+/// Identifies where an unverified wrap was loaded from, since it could claim to be anyone
+pub(super) fn source_key(source: &Url) -> String {
+    hex::encode(Sha256::digest(source.as_str()))
+}
+
+fn decider_key(metadata: &WrapMetdata, source: &Url) -> String {
+    match &metadata.verified_publisher {
+        Some(publisher) => publisher.name().into(),
+        None => format!("unverified:{}", source_key(source)),
+    }
+}
+
+fn wrap_key(metadata: &WrapMetdata) -> String {
+    format!("{}@{}", metadata.name, metadata.version)
+}
+
+impl ConsentStore {
+    /// Read decisions from a file, if any. Without a path, decisions are forgotten on exit
+    pub fn new(path: Option<PathBuf>) -> Result<Self, ConsentError> {
+        let decisions = match &path {
+            Some(path) if path.exists() => {
+                serde_json::from_slice(&fs::read(path)?).map_err(ConsentError::InvalidFile)?
+            }
+            _ => Decisions::default(),
+        };
+
+        Ok(Self { path, decisions })
+    }
+
+    fn save(&self) -> Result<(), ConsentError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let decisions =
+            serde_json::to_vec_pretty(&self.decisions).map_err(ConsentError::InvalidFile)?;
+        fs::write(path, decisions)?;
+        Ok(())
+    }
+
+    /// Whether the user granted a capability to a wrap, or None if they weren't asked yet
+    pub fn decision(
+        &self,
+        metadata: &WrapMetdata,
+        source: &Url,
+        capability: Capability,
+    ) -> Option<bool> {
+        self.decisions
+            .get(&decider_key(metadata, source))?
+            .get(&wrap_key(metadata))?
+            .get(capability.as_str())
+            .copied()
+    }
+
+    /// Remember the user's decision for a capability the wrap asked for
+    pub fn decide(
+        &mut self,
+        metadata: &WrapMetdata,
+        source: &Url,
+        capability: Capability,
+        granted: bool,
+    ) -> Result<(), ConsentError> {
+        if !metadata.capabilities.contains(&capability) {
+            return Err(ConsentError::NotRequested(capability));
+        }
+
+        self.decisions
+            .entry(decider_key(metadata, source))
+            .or_default()
+            .entry(wrap_key(metadata))
+            .or_default()
+            .insert(capability.as_str().into(), granted);
+        self.save()
+    }
+
+    /// Forget every decision made for a wrap, so the user is asked again
+    pub fn reset(&mut self, metadata: &WrapMetdata, source: &Url) -> Result<(), ConsentError> {
+        if let Some(wraps) = self.decisions.get_mut(&decider_key(metadata, source)) {
+            wraps.remove(&wrap_key(metadata));
+        }
+        self.save()
+    }
+
+    /// Capabilities the wrap asked for and the user approved
+    pub fn granted(&self, metadata: &WrapMetdata, source: &Url) -> HashSet<Capability> {
+        metadata
+            .capabilities
+            .iter()
+            .copied()
+            .filter(|capability| self.decision(metadata, source, *capability) == Some(true))
+            .collect()
+    }
+
+    /// Capabilities the wrap asked for that the user hasn't decided on yet
+    pub fn undecided(&self, metadata: &WrapMetdata, source: &Url) -> Vec<Capability> {
+        let mut capabilities: Vec<_> = metadata
+            .capabilities
+            .iter()
+            .copied()
+            .filter(|capability| self.decision(metadata, source, *capability).is_none())
+            .collect();
+        capabilities.sort();
+        capabilities.dedup();
+        capabilities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(verified_publisher: Option<Publisher>) -> WrapMetdata {
+        WrapMetdata {
+            name: "my_wrap".into(),
+            version: "0.1.0".into(),
+            wrap_type: WrapType::Test,
+            description: String::new(),
+            publisher: Publisher::new("Harmony", vec![]),
+            interface_version: InterfaceVersion::new(),
+            capabilities: vec![Capability::Storage],
+            icon: None,
+            homepage: None,
+            supported_mimetypes: vec![],
+            verified_publisher,
+        }
+    }
+
+    #[test]
+    fn test_unverified_keyed_by_source() {
+        let mut consent = ConsentStore::new(None).unwrap();
+        let source = Url::parse("file:///wraps/my_wrap.wasm").unwrap();
+        let impostor = Url::parse("file:///downloads/my_wrap.wasm").unwrap();
+
+        let unverified = metadata(None);
+        consent
+            .decide(&unverified, &source, Capability::Storage, true)
+            .unwrap();
+        assert!(consent
+            .granted(&unverified, &source)
+            .contains(&Capability::Storage));
+        assert_eq!(
+            consent.undecided(&unverified, &impostor),
+            vec![Capability::Storage]
+        );
+
+        // Verified publishers keep their decisions wherever the wrap is loaded from
+        let verified = metadata(Some(Publisher::new("Harmony", vec![])));
+        consent
+            .decide(&verified, &source, Capability::Storage, true)
+            .unwrap();
+        assert!(consent
+            .granted(&verified, &impostor)
+            .contains(&Capability::Storage));
+    }
+}
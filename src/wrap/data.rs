@@ -0,0 +1,345 @@
+use bevy::utils::{HashMap, HashSet};
+use hmny_common::host::STREAM_ERROR;
+use hmny_common::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// Payloads up to this size are sent inline, larger ones are streamed
+pub const INLINE_DATA_LIMIT: usize = 1024 * 1024;
+
+/// Most bytes a wrap can read from a stream in a single host call
+pub const STREAM_CHUNK_LIMIT: u64 = 1024 * 1024;
+
+enum ReferencedData {
+    Content(Arc<[u8]>),
+    File(PathBuf),
+}
+
+impl ReferencedData {
+    fn open(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(match self {
+            Self::Content(content) => Box::new(Cursor::new(content.clone())),
+            Self::File(path) => Box::new(File::open(path)?),
+        })
+    }
+}
+
+/// A stream created by the host, read by whichever wraps it is sent to
+///
+/// Everything read is kept, so the next wrap trying the same signal reads the stream from the start
+struct Source {
+    reader: Box<dyn Read + Send>,
+    buffered: Vec<u8>,
+    ended: bool,
+    /// Scopes the stream was sent to that haven't ended yet
+    holders: usize,
+}
+
+impl Source {
+    fn read_at(&mut self, position: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        if position == self.buffered.len() && !self.ended {
+            let read = self.reader.read(buffer)?;
+            self.ended = read == 0 && !buffer.is_empty();
+            self.buffered.extend_from_slice(&buffer[..read]);
+            return Ok(read);
+        }
+
+        let available = self.buffered.get(position..).unwrap_or_default();
+        let read = available.len().min(buffer.len());
+        buffer[..read].copy_from_slice(&available[..read]);
+        Ok(read)
+    }
+}
+
+enum OpenStream {
+    /// Opened by the host for this scope alone, such as a response or a reference
+    Reader(Box<dyn Read + Send>),
+    /// How far this scope has read a source
+    Source { position: usize },
+}
+
+#[derive(Default)]
+struct DataStoreInner {
+    next_scope_id: u64,
+    next_stream_id: u64,
+    sources: HashMap<u64, Source>,
+    /// Sources each scope was sent
+    scopes: HashMap<u64, HashSet<u64>>,
+    /// Streams by scope and id
+    streams: HashMap<(u64, u64), OpenStream>,
+    references: HashMap<DataReference, ReferencedData>,
+}
+
+impl DataStoreInner {
+    fn read_stream(&mut self, scope_id: u64, stream_id: u64, buffer: &mut [u8]) -> Option<usize> {
+        // Sources are opened the first time the scope reads them
+        let can_open = self
+            .scopes
+            .get(&scope_id)
+            .is_some_and(|sources| sources.contains(&stream_id));
+        if can_open && self.sources.contains_key(&stream_id) {
+            self.streams
+                .entry((scope_id, stream_id))
+                .or_insert(OpenStream::Source { position: 0 });
+        }
+
+        match self.streams.get_mut(&(scope_id, stream_id))? {
+            OpenStream::Reader(reader) => reader.read(buffer).ok(),
+            OpenStream::Source { position } => {
+                let read = self
+                    .sources
+                    .get_mut(&stream_id)?
+                    .read_at(*position, buffer)
+                    .ok()?;
+                *position += read;
+                Some(read)
+            }
+        }
+    }
+
+    fn end_scope(&mut self, scope_id: u64) {
+        self.streams.retain(|(scope, _), _| *scope != scope_id);
+        for stream_id in self.scopes.remove(&scope_id).unwrap_or_default() {
+            let Some(source) = self.sources.get_mut(&stream_id) else {
+                continue;
+            };
+            source.holders -= 1;
+            if source.holders == 0 {
+                self.sources.remove(&stream_id);
+            }
+        }
+    }
+}
+
+/// How a stream id appears inside of an encoded signal
+fn encoded_stream_id(stream_id: u64) -> Vec<u8> {
+    bincode::encode_to_vec(stream_id, bincode::config::standard())
+        .expect("Integers can always be encoded")
+}
+
+/// Payloads handed to wraps that are too large, or too costly, to copy into a signal
+///
+/// Wraps only ever read streams through the [`DataScope`] of the signal they are handling. Streams
+/// created by the host belong to the signals they are sent in, and are closed once those complete
+#[derive(Clone, Default)]
+pub struct DataStore {
+    inner: Arc<Mutex<DataStoreInner>>,
+}
+
+impl DataStore {
+    fn lock(&self) -> std::sync::MutexGuard<'_, DataStoreInner> {
+        // Streams are plain data, so they remain usable even if a reader panicked
+        self.inner.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    /// Send small payloads inline and stream the others
+    pub fn bytes(&self, bytes: Vec<u8>) -> DataType {
+        if bytes.len() <= INLINE_DATA_LIMIT {
+            return DataType::Bytes(bytes);
+        }
+        let len = bytes.len() as u64;
+        self.stream(Cursor::new(bytes), Some(len))
+    }
+
+    /// Stream data to the wraps of the next signal it is sent in
+    ///
+    /// The stream is kept until that signal completes, so it must not be sent in any other
+    pub fn stream(&self, reader: impl Read + Send + 'static, len: Option<u64>) -> DataType {
+        let mut inner = self.lock();
+
+        // Ids of sources are random and as long as they can be, so they can be found in signals
+        // without being mistaken for anything else, and can't be guessed by other wraps
+        let stream_id = loop {
+            let stream_id = rand::random::<u64>() | 1 << 63;
+            if stream_id != STREAM_ERROR && !inner.sources.contains_key(&stream_id) {
+                break stream_id;
+            }
+        };
+        inner.sources.insert(
+            stream_id,
+            Source {
+                reader: Box::new(reader),
+                buffered: Vec::new(),
+                ended: false,
+                holders: 0,
+            },
+        );
+        DataType::Stream { stream_id, len }
+    }
+
+    /// Keep content around for wraps to pull, referenced by its hash
+    pub fn reference_content(&self, content: impl Into<Arc<[u8]>>) -> DataType {
+        let content = content.into();
+        let reference = DataReference::Hash(Sha256::digest(&content).into());
+        self.lock()
+            .references
+            .insert(reference.clone(), ReferencedData::Content(content));
+        DataType::Reference(reference)
+    }
+
+    /// Let wraps pull a file, referenced by its url
+    pub fn reference_file<P: AsRef<Path>>(&self, path: P) -> io::Result<DataType> {
+        let path = fs::canonicalize(path)?;
+        let url = Url::from_file_path(&path)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path is not absolute"))?;
+        let reference = DataReference::Url(url.into());
+        self.lock()
+            .references
+            .insert(reference.clone(), ReferencedData::File(path));
+        Ok(DataType::Reference(reference))
+    }
+
+    pub fn forget_reference(&self, reference: &DataReference) {
+        self.lock().references.remove(reference);
+    }
+
+    /// Start handling an encoded signal, giving access to the streams sent in it
+    pub fn scope(&self, input_signal_bytes: &[u8]) -> DataScope {
+        let mut inner = self.lock();
+        inner.next_scope_id += 1;
+        let scope_id = inner.next_scope_id;
+
+        let mut sent = HashSet::new();
+        for (stream_id, source) in inner.sources.iter_mut() {
+            let needle = encoded_stream_id(*stream_id);
+            if input_signal_bytes
+                .windows(needle.len())
+                .any(|window| window == needle)
+            {
+                source.holders += 1;
+                sent.insert(*stream_id);
+            }
+        }
+        inner.scopes.insert(scope_id, sent);
+
+        DataScope {
+            store: self.clone(),
+            scope_id,
+        }
+    }
+}
+
+/// Streams a wrap can access while handling a single signal, closed once the scope is dropped
+pub struct DataScope {
+    store: DataStore,
+    scope_id: u64,
+}
+
+impl DataScope {
+    pub(super) fn open_stream(&self, reader: Box<dyn Read + Send>) -> u64 {
+        let mut inner = self.store.lock();
+        inner.next_stream_id += 1;
+        let stream_id = inner.next_stream_id;
+        inner
+            .streams
+            .insert((self.scope_id, stream_id), OpenStream::Reader(reader));
+        stream_id
+    }
+
+    /// Open a new stream over referenced data, returning its id
+    pub fn open_reference(&self, reference: &DataReference) -> Option<u64> {
+        let reader = self.store.lock().references.get(reference)?.open().ok()?;
+        Some(self.open_stream(reader))
+    }
+
+    /// Read the next chunk of a stream, returning how many bytes were read. Zero means it has ended
+    pub fn read_stream(&self, stream_id: u64, buffer: &mut [u8]) -> Option<usize> {
+        self.store
+            .lock()
+            .read_stream(self.scope_id, stream_id, buffer)
+    }
+
+    pub fn close_stream(&self, stream_id: u64) {
+        self.store
+            .lock()
+            .streams
+            .remove(&(self.scope_id, stream_id));
+    }
+}
+
+impl Drop for DataScope {
+    fn drop(&mut self) {
+        self.store.lock().end_scope(self.scope_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream_id(data: &DataType) -> u64 {
+        match data {
+            DataType::Stream { stream_id, .. } => *stream_id,
+            _ => panic!("Expected a stream"),
+        }
+    }
+
+    fn read_all(scope: &DataScope, stream_id: u64) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut buffer = [0; 3];
+        loop {
+            match scope.read_stream(stream_id, &mut buffer)? {
+                0 => return Some(bytes),
+                read => bytes.extend_from_slice(&buffer[..read]),
+            }
+        }
+    }
+
+    fn signal_with(data: &DataType) -> Vec<u8> {
+        bincode::encode_to_vec(data, bincode::config::standard()).unwrap()
+    }
+
+    #[test]
+    fn test_streams_are_scoped_to_their_signal() {
+        let store = DataStore::default();
+        let data = store.stream(Cursor::new(b"hello".to_vec()), Some(5));
+        let stream_id = stream_id(&data);
+
+        let other = store.scope(&signal_with(&DataType::String("hello".into())));
+        assert_eq!(other.read_stream(stream_id, &mut [0; 8]), None);
+
+        let scope = store.scope(&signal_with(&data));
+        assert_eq!(read_all(&scope, stream_id), Some(b"hello".to_vec()));
+
+        // Streams opened for a scope can't be read from any other
+        let opened = scope.open_stream(Box::new(Cursor::new(b"response".to_vec())));
+        assert_eq!(other.read_stream(opened, &mut [0; 8]), None);
+        other.close_stream(opened);
+        assert_eq!(read_all(&scope, opened), Some(b"response".to_vec()));
+    }
+
+    #[test]
+    fn test_fallback_reads_stream_again() {
+        let store = DataStore::default();
+        let data = store.stream(Cursor::new(b"hello world".to_vec()), None);
+        let stream_id = stream_id(&data);
+        let signal = signal_with(&data);
+        let _signal_scope = store.scope(&signal);
+
+        // The first candidate only reads part of the stream before failing
+        let first = store.scope(&signal);
+        assert_eq!(first.read_stream(stream_id, &mut [0; 4]), Some(4));
+        drop(first);
+
+        let second = store.scope(&signal);
+        assert_eq!(read_all(&second, stream_id), Some(b"hello world".to_vec()));
+    }
+
+    #[test]
+    fn test_streams_close_with_their_signal() {
+        let store = DataStore::default();
+        let data = store.stream(Cursor::new(b"unread".to_vec()), None);
+        let scope = store.scope(&signal_with(&data));
+        scope.open_stream(Box::new(Cursor::new(Vec::new())));
+        drop(scope);
+
+        let inner = store.lock();
+        assert!(inner.sources.is_empty());
+        assert!(inner.streams.is_empty());
+        assert!(inner.scopes.is_empty());
+    }
+}
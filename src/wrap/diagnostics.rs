@@ -0,0 +1,184 @@
+use super::loader::path_to_url;
+use super::{WrapLoadFailed, WrapLoaded, WrapReplaced, WrapWatcherDisconnected};
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub struct WrapDiagnosticsPlugin;
+
+/// Wraps that failed to load, by path, along with the reason
+#[derive(Resource, Default)]
+pub struct WrapDiagnostics {
+    failures: BTreeMap<PathBuf, String>,
+    /// Changes to wraps are no longer picked up
+    pub watcher_disconnected: bool,
+}
+
+impl WrapDiagnostics {
+    pub fn failures(&self) -> impl Iterator<Item = (&PathBuf, &String)> {
+        self.failures.iter()
+    }
+
+    /// Forget about a previous failure, once the wrap was loaded successfully
+    pub fn clear(&mut self, path: &Path) {
+        self.failures.remove(path);
+    }
+}
+
+/// Failures are kept under the same path wraps are loaded from, however the path was written
+fn canonical_path(path: &Path) -> PathBuf {
+    path_to_url(path)
+        .ok()
+        .and_then(|url| url.to_file_path().ok())
+        .unwrap_or_else(|| path.into())
+}
+
+#[derive(Component)]
+struct WrapDiagnosticsText;
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        WrapDiagnosticsText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 14.,
+                color: Color::rgb(0.7, 0.1, 0.1),
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.),
+            left: Val::Px(10.),
+            ..default()
+        }),
+    ));
+}
+
+fn record_load_failures(
+    mut failures: EventReader<WrapLoadFailed>,
+    mut loaded: EventReader<WrapLoaded>,
+    mut replaced: EventReader<WrapReplaced>,
+    mut watcher_disconnected: EventReader<WrapWatcherDisconnected>,
+    mut diagnostics: ResMut<WrapDiagnostics>,
+) {
+    if watcher_disconnected.read().count() > 0 {
+        diagnostics.watcher_disconnected = true;
+    }
+
+    // Wraps that loaded successfully since failing no longer need reporting
+    let sources = loaded
+        .read()
+        .map(|event| &event.source)
+        .chain(replaced.read().map(|event| &event.source));
+    for path in sources.filter_map(|source| source.to_file_path().ok()) {
+        if diagnostics.failures.contains_key(&path) {
+            diagnostics.clear(&path);
+        }
+    }
+
+    for WrapLoadFailed { path, error } in failures.read() {
+        diagnostics
+            .failures
+            .insert(canonical_path(path), format!("{:?}", error));
+    }
+}
+
+fn update_diagnostics_text(
+    diagnostics: Res<WrapDiagnostics>,
+    mut texts: Query<&mut Text, With<WrapDiagnosticsText>>,
+) {
+    if !diagnostics.is_changed() {
+        return;
+    }
+
+    let mut value = String::new();
+    if diagnostics.watcher_disconnected {
+        value.push_str("Wrap file watcher disconnected, changes to wraps are no longer loaded\n");
+    }
+    if !diagnostics.failures.is_empty() {
+        value.push_str("Wraps that failed to load:");
+        for (path, reason) in diagnostics.failures() {
+            let file_name = path.file_name().unwrap_or(path.as_os_str());
+            value.push_str(&format!("\n{}: {}", file_name.to_string_lossy(), reason));
+        }
+    }
+
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = value.clone();
+    }
+}
+
+impl Plugin for WrapDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WrapDiagnostics>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (record_load_failures, update_diagnostics_text).chain(),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{test_dir, WrapLoaderError};
+    use super::*;
+    use hmny_common::prelude::*;
+    use std::fs;
+
+    fn metadata() -> WrapMetdata {
+        WrapMetdata {
+            name: "my_wrap".into(),
+            version: "0.1.0".into(),
+            wrap_type: WrapType::Test,
+            description: String::new(),
+            publisher: Publisher::new("Harmony", vec![]),
+            interface_version: InterfaceVersion::new(),
+            capabilities: vec![],
+            icon: None,
+            homepage: None,
+            supported_mimetypes: vec![],
+            verified_publisher: None,
+        }
+    }
+
+    #[test]
+    fn test_failure_cleared_once_loaded() {
+        let mut app = App::new();
+        app.add_event::<WrapLoadFailed>()
+            .add_event::<WrapLoaded>()
+            .add_event::<WrapReplaced>()
+            .add_event::<WrapWatcherDisconnected>()
+            .init_resource::<WrapDiagnostics>()
+            .add_systems(Update, record_load_failures);
+
+        let dir = test_dir("diagnostics");
+        let path = dir.join("my_wrap.wasm");
+        fs::write(&path, []).unwrap();
+
+        // Paths found while searching aren't canonical, unlike the sources of loaded wraps
+        app.world.send_event(WrapLoadFailed {
+            path: dir.join(".").join("my_wrap.wasm"),
+            error: WrapLoaderError::InvalidMetdata,
+        });
+        app.update();
+        assert_eq!(
+            app.world.resource::<WrapDiagnostics>().failures().count(),
+            1
+        );
+
+        app.world.send_event(WrapLoaded {
+            key: WrapKey::Other(WrapType::Test, "my_wrap".into()),
+            metadata: metadata(),
+            source: path_to_url(&path).unwrap(),
+        });
+        app.update();
+        assert_eq!(
+            app.world.resource::<WrapDiagnostics>().failures().count(),
+            0
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
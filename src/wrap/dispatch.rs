@@ -0,0 +1,144 @@
+use super::{signal_candidates_named, SignalError, Wraps};
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+use hmny_common::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Tells apart the responses to requests sent for the same key
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    /// A new id, never handed out before
+    pub fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ask the wraps of a key to handle a signal on the async compute pool. The result is delivered as a [`SignalResponse`]
+#[derive(Event)]
+pub struct SignalRequest<Signal: HarmonySignal> {
+    pub id: RequestId,
+    pub key: WrapKey,
+    pub signal: Signal,
+}
+
+impl<Signal: HarmonySignal> SignalRequest<Signal> {
+    pub fn new(key: WrapKey, signal: Signal) -> Self {
+        Self {
+            id: RequestId::new(),
+            key,
+            signal,
+        }
+    }
+}
+
+#[derive(Event)]
+pub struct SignalResponse<Signal: HarmonySignal> {
+    /// Id of the request this responds to
+    pub id: RequestId,
+    pub key: WrapKey,
+    /// Name of the wrap that handled the signal, if any did
+    pub wrap_name: Option<String>,
+    pub result: Result<Signal::ResponseType, SignalError>,
+}
+
+type SignalTask<Signal> =
+    Task<Result<(String, <Signal as HarmonySignal>::ResponseType), SignalError>>;
+
+#[derive(Resource)]
+struct PendingSignals<Signal: HarmonySignal> {
+    tasks: Vec<(RequestId, WrapKey, SignalTask<Signal>)>,
+}
+
+impl<Signal: HarmonySignal> Default for PendingSignals<Signal> {
+    fn default() -> Self {
+        Self { tasks: Vec::new() }
+    }
+}
+
+fn dispatch_signal_requests<Signal: HarmonySignal>(
+    wraps: Res<Wraps>,
+    mut requests: ResMut<Events<SignalRequest<Signal>>>,
+    mut pending: ResMut<PendingSignals<Signal>>,
+) {
+    let pool = AsyncComputeTaskPool::get();
+    for SignalRequest { id, key, signal } in requests.drain() {
+        // Even without candidates, the streams sent in the signal must be released
+        let candidates = wraps.get_candidates(&key);
+        let data = wraps.data.clone();
+        let task = pool.spawn(async move { signal_candidates_named(&data, &candidates, signal) });
+        pending.tasks.push((id, key, task));
+    }
+}
+
+fn poll_signal_responses<Signal: HarmonySignal>(
+    mut wraps: ResMut<Wraps>,
+    mut pending: ResMut<PendingSignals<Signal>>,
+    mut responses: EventWriter<SignalResponse<Signal>>,
+) {
+    pending.tasks.retain_mut(
+        |(id, key, task)| match future::block_on(future::poll_once(task)) {
+            Some(result) => {
+                wraps.handle_faults(key);
+                let (wrap_name, result) = match result {
+                    Ok((wrap_name, response)) => (Some(wrap_name), Ok(response)),
+                    Err(error) => (None, Err(error)),
+                };
+                responses.send(SignalResponse {
+                    id: *id,
+                    key: key.clone(),
+                    wrap_name,
+                    result,
+                });
+                false
+            }
+            None => true,
+        },
+    );
+}
+
+pub trait SignalAppExt {
+    /// Allow sending signals of this type through [`SignalRequest`] events
+    fn add_signal<Signal: HarmonySignal>(&mut self) -> &mut Self;
+
+    /// Allow sending the signals of every interface
+    fn add_signals(&mut self) -> &mut Self;
+}
+
+struct AddSignals<'a>(&'a mut App);
+
+impl SignalVisitor for AddSignals<'_> {
+    fn visit<Signal: HarmonySignal>(&mut self) {
+        self.0.add_signal::<Signal>();
+    }
+}
+
+impl SignalAppExt for App {
+    fn add_signal<Signal: HarmonySignal>(&mut self) -> &mut Self {
+        self.add_event::<SignalRequest<Signal>>()
+            .add_event::<SignalResponse<Signal>>()
+            .init_resource::<PendingSignals<Signal>>()
+            .add_systems(
+                Update,
+                (
+                    dispatch_signal_requests::<Signal>,
+                    poll_signal_responses::<Signal>,
+                )
+                    .chain(),
+            )
+    }
+
+    fn add_signals(&mut self) -> &mut Self {
+        visit_signals(&mut AddSignals(self));
+        self
+    }
+}
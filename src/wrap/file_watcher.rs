@@ -1,19 +1,32 @@
-use super::Wraps;
-use bevy::prelude::*;
+use super::{WrapLoadFailed, WrapLoaderError, WrapOrigin, WrapSearchPaths, Wraps};
+use bevy::{prelude::*, utils::HashMap};
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Result, Watcher};
 use std::{
     fs,
-    path::Path,
+    path::{Path, PathBuf},
     sync::{
         mpsc::{Receiver, TryRecvError},
         Arc, Mutex,
     },
+    time::{Duration, Instant},
 };
 
-const WRAPS_LOAD_DIR: &str = "./target/wasm32-unknown-unknown/release";
+/// Builds write wraps in several steps, so changes are only handled once a file has settled
+const WRAP_CHANGE_DEBOUNCE: Duration = Duration::from_millis(300);
+/// Bare wasm modules and signed wrap packages
+const WRAP_EXTENSIONS: [&str; 2] = ["wasm", "wrap"];
+
+pub fn is_wrap_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| WRAP_EXTENSIONS.iter().any(|wrap_ext| ext == *wrap_ext))
+}
 
 pub struct WrapFileWatcherPlugin;
 
+/// Sent once if the file watcher stops, after which changes to wraps are no longer picked up
+#[derive(Event, Debug)]
+pub struct WrapWatcherDisconnected;
+
 struct WrapFileWatcherInner {
     watcher: RecommendedWatcher,
     receiver: Mutex<Receiver<Result<Event>>>,
@@ -24,7 +37,10 @@ impl WrapFileWatcherInner {
         let (sender, receiver) = std::sync::mpsc::channel();
         let watcher = RecommendedWatcher::new(
             move |res| {
-                sender.send(res).expect("Watch event send failure.");
+                // Only fails once the receiver is dropped, along with the watcher
+                if let Err(error) = sender.send(res) {
+                    warn!("Could not forward file watcher event: {:?}", error);
+                }
             },
             default(),
         )
@@ -46,71 +62,160 @@ pub struct WrapFileWatcher {
     inner: Arc<WrapFileWatcherInner>,
 }
 
-impl Default for WrapFileWatcher {
-    fn default() -> Self {
+impl FromWorld for WrapFileWatcher {
+    fn from_world(world: &mut World) -> Self {
         let mut inner = WrapFileWatcherInner::new();
 
-        let path = Path::new(WRAPS_LOAD_DIR);
-        let _ = fs::create_dir_all(path);
-        inner.watch(path).expect("Failed to watch path.");
+        for (path, origin) in world.resource::<WrapSearchPaths>().iter() {
+            // System directories are managed by the system's package manager
+            if origin != WrapOrigin::System {
+                let _ = fs::create_dir_all(path);
+            }
+            if !path.is_dir() {
+                info!("Skipping missing wrap search path {:?}", path);
+                continue;
+            }
+            if let Err(error) = inner.watch(path) {
+                error!("Failed to watch wrap search path {:?}: {:?}", path, error);
+            }
+        }
 
         let inner = Arc::new(inner);
         Self { inner }
     }
 }
 
-fn wraps_load_from_dir_system(mut wraps: ResMut<Wraps>) {
-    let paths = fs::read_dir(WRAPS_LOAD_DIR).expect("Failed to read wraps load directory");
-
-    paths.into_iter().for_each(|path| {
-        let path = path.unwrap().path();
+/// Load a wrap, reporting it as failed rather than giving up on the others
+fn load_wrap(
+    wraps: &mut Wraps,
+    path: &Path,
+    origin: WrapOrigin,
+    failures: &mut EventWriter<WrapLoadFailed>,
+) {
+    match wraps.load_from_path(path, origin) {
+        Ok(()) => {}
+        Err(WrapLoaderError::Shadowed(id)) => {
+            info!(
+                "Not loading {:?}, {:?} was found with a higher precedence",
+                path, id
+            );
+        }
+        Err(error) => {
+            error!("Error while attempting to load plugin {:?}", path);
+            error!("    {:?}", error);
+            failures.send(WrapLoadFailed {
+                path: path.into(),
+                error,
+            });
+        }
+    }
+}
 
-        match path.extension() {
-            Some(ext) if ext == "wasm" => {
-                if let Err(res) = wraps.load_from_path(path.clone()) {
-                    error!("Error while attempting to load plugin {:?}", path);
-                    error!("    {:?}", res);
-                }
+/// Load every wrap in the search paths that isn't loaded yet, highest precedence first
+fn load_search_paths(
+    wraps: &mut Wraps,
+    search_paths: &WrapSearchPaths,
+    failures: &mut EventWriter<WrapLoadFailed>,
+) {
+    for (dir, origin) in search_paths.iter() {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+
+        for path in entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+        {
+            if is_wrap_file(&path) && wraps.get_loaded_from_path(&path).is_none() {
+                load_wrap(wraps, &path, origin, failures);
             }
-            _ => {}
         }
-    });
+    }
 }
 
-fn wraps_file_watcher_system(mut wraps: ResMut<Wraps>, file_watcher: Res<WrapFileWatcher>) {
+fn wraps_load_from_search_paths_system(
+    mut wraps: ResMut<Wraps>,
+    search_paths: Res<WrapSearchPaths>,
+    mut failures: EventWriter<WrapLoadFailed>,
+) {
+    load_search_paths(&mut wraps, &search_paths, &mut failures);
+}
+
+fn wraps_file_watcher_system(
+    mut wraps: ResMut<Wraps>,
+    file_watcher: Res<WrapFileWatcher>,
+    search_paths: Res<WrapSearchPaths>,
+    mut changes: Local<HashMap<PathBuf, (EventKind, Instant)>>,
+    mut disconnected: Local<bool>,
+    mut failures: EventWriter<WrapLoadFailed>,
+    mut watcher_disconnected: EventWriter<WrapWatcherDisconnected>,
+) {
     if let Ok(receiver) = file_watcher.inner.receiver.lock() {
-        loop {
+        while !*disconnected {
             let Event { kind, paths, .. } = match receiver.try_recv() {
-                Ok(result) => result.unwrap(),
+                Ok(Ok(event)) => event,
+                Ok(Err(error)) => {
+                    warn!("File watcher error: {:?}", error);
+                    continue;
+                }
                 Err(TryRecvError::Empty) => break,
-                Err(TryRecvError::Disconnected) => panic!("FilesystemWatcher disconnected."),
+                Err(TryRecvError::Disconnected) => {
+                    // Wraps keep running, they just won't be reloaded anymore
+                    error!("Wrap file watcher disconnected");
+                    watcher_disconnected.send(WrapWatcherDisconnected);
+                    *disconnected = true;
+                    break;
+                }
             };
 
-            paths.iter().for_each(|path| {
-                // We only load wasm files for now
-                match path.extension() {
-                    Some(ext) if ext == "wasm" => match kind {
-                        EventKind::Create(_) => {
-                            wraps.load_from_path(path).unwrap();
-                        }
-                        EventKind::Remove(_) => {
-                            wraps.unload_from_path(path).unwrap();
+            paths.into_iter().for_each(|path| {
+                if is_wrap_file(&path) {
+                    match kind {
+                        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_) => {
+                            // Only the latest change to a file matters
+                            changes.insert(path, (kind, Instant::now()));
                         }
+                        EventKind::Access(_) => {}
                         _ => {
                             warn!("Unknown file watcher event: {:?} {:?}", path, kind);
                         }
-                    },
-                    _ => {}
+                    }
                 }
             });
         }
     }
+
+    let settled: Vec<PathBuf> = changes
+        .iter()
+        .filter(|(_, (_, changed_at))| changed_at.elapsed() >= WRAP_CHANGE_DEBOUNCE)
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    for path in settled {
+        let (kind, _) = changes.remove(&path).unwrap();
+        let Some(origin) = search_paths.origin_of(&path) else {
+            continue;
+        };
+
+        match kind {
+            EventKind::Remove(_) => match wraps.unload_from_path(&path) {
+                // Any wrap it was shadowing can now be loaded
+                Ok(()) => load_search_paths(&mut wraps, &search_paths, &mut failures),
+                Err(error) => warn!("Could not unload {:?}: {:?}", path, error),
+            },
+            // Creating a file that is already loaded, or modifying it, replaces the wrap
+            // A wrap that fails to reload keeps running its previous build
+            _ => load_wrap(&mut wraps, &path, origin, &mut failures),
+        }
+    }
 }
 
 impl Plugin for WrapFileWatcherPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<WrapFileWatcher>()
-            .add_systems(PreStartup, wraps_load_from_dir_system)
+        app.add_event::<WrapWatcherDisconnected>()
+            .init_resource::<WrapSearchPaths>()
+            .init_resource::<WrapFileWatcher>()
+            .add_systems(PreStartup, wraps_load_from_search_paths_system)
             .add_systems(PostUpdate, wraps_file_watcher_system);
     }
 }
@@ -0,0 +1,401 @@
+use super::data::{DataScope, DataStore, STREAM_CHUNK_LIMIT};
+use super::loader::WrapId;
+use super::router::WrapRouter;
+use super::storage::WrapStorage;
+use bevy::prelude::*;
+use hmny_common::host::{LogLevel, LogRecord, HOST_MODULE, STREAM_ERROR};
+use hmny_common::prelude::*;
+use hmny_common::storage::{StorageError, StorageResult};
+use rand::RngCore;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+use wasmer::{Function, FunctionEnv, FunctionEnvMut, Imports, Memory, RuntimeError, Store};
+
+/// State shared with the host functions of a single wrap instance
+pub struct HostEnv {
+    pub wrap_name: String,
+    /// Id the wrap was loaded under, once loaded
+    pub wrap_id: Option<WrapId>,
+    /// Streams and references the wrap can read from
+    pub data: DataStore,
+    /// Streams of the signal the wrap is handling, if any
+    pub scope: Option<DataScope>,
+    /// Capabilities the user approved. Host functions behind any other capability refuse to run
+    pub capabilities: HashSet<Capability>,
+    /// Other wraps this wrap can send signals to
+    pub router: WrapRouter,
+    /// Entries the wrap keeps between sessions
+    pub storage: WrapStorage,
+    /// Reported by the wrap's panic hook during the current signal
+    pub panic: Option<LogRecord>,
+    memory: Option<Memory>,
+}
+
+impl HostEnv {
+    pub fn new() -> Self {
+        Self {
+            wrap_name: "<unknown wrap>".into(),
+            wrap_id: None,
+            data: DataStore::default(),
+            scope: None,
+            capabilities: HashSet::new(),
+            router: WrapRouter::default(),
+            storage: WrapStorage::default(),
+            panic: None,
+            memory: None,
+        }
+    }
+
+    /// The wrap's exported memory only exists once instantiated, so it must be provided afterwards
+    pub fn set_memory(&mut self, memory: Memory) {
+        self.memory = Some(memory);
+    }
+
+    /// Called first by every host function behind a capability
+    ///
+    /// Denials are returned to the wrap instead of trapping, so it can carry on without it
+    pub fn require(&self, capability: Capability) -> Result<(), WrapError> {
+        if self.capabilities.contains(&capability) {
+            return Ok(());
+        }
+        warn!(
+            "{} was denied the {} capability",
+            self.wrap_name, capability
+        );
+        Err(WrapError::CapabilityDenied(capability))
+    }
+}
+
+fn get_memory<'a>(env: &'a FunctionEnvMut<HostEnv>) -> Result<&'a Memory, RuntimeError> {
+    env.data()
+        .memory
+        .as_ref()
+        .ok_or_else(|| RuntimeError::new("host function called before memory was initialized"))
+}
+
+/// Make sure a guest buffer lies within its memory, before allocating anything for it
+fn checked_len(env: &FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> Result<usize, RuntimeError> {
+    let data_size = get_memory(env)?.view(env).data_size();
+    match ptr.checked_add(len) {
+        Some(end) if end <= data_size => Ok(len as usize),
+        _ => Err(RuntimeError::new(format!(
+            "buffer {}..{} is out of bounds of memory of size {}",
+            ptr,
+            ptr.saturating_add(len),
+            data_size
+        ))),
+    }
+}
+
+fn read_bytes(env: &FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> Result<Vec<u8>, RuntimeError> {
+    let mut buffer = vec![0; checked_len(env, ptr, len)?];
+    let view = get_memory(env)?.view(env);
+    view.read(ptr, &mut buffer)
+        .map_err(|error| RuntimeError::new(format!("{}", error)))?;
+    Ok(buffer)
+}
+
+fn write_bytes(env: &FunctionEnvMut<HostEnv>, ptr: u64, bytes: &[u8]) -> Result<(), RuntimeError> {
+    let view = get_memory(env)?.view(env);
+    view.write(ptr, bytes)
+        .map_err(|error| RuntimeError::new(format!("{}", error)))
+}
+
+/// Forward a wrap's log record to tracing, inside of a span named after the wrap
+fn emit_log(wrap_name: &str, record: &LogRecord) {
+    let _span = info_span!("wrap", name = wrap_name).entered();
+    let module = record.module_path.as_deref().unwrap_or_default();
+    let location = match (&record.file, record.line) {
+        (Some(file), Some(line)) => format!("{}:{}", file, line),
+        _ => String::new(),
+    };
+    let message = &record.message;
+    match record.level {
+        LogLevel::Error => error!(module, location = %location, "{}", message),
+        LogLevel::Warn => warn!(module, location = %location, "{}", message),
+        LogLevel::Info => info!(module, location = %location, "{}", message),
+        LogLevel::Debug => debug!(module, location = %location, "{}", message),
+        LogLevel::Trace => trace!(module, location = %location, "{}", message),
+    }
+}
+
+fn log(env: FunctionEnvMut<HostEnv>, level: u32, ptr: u64, len: u64) -> Result<(), RuntimeError> {
+    let level = LogLevel::from_u32(level)
+        .ok_or_else(|| RuntimeError::new(format!("invalid log level {}", level)))?;
+    let record = LogRecord {
+        level,
+        message: String::from_utf8_lossy(&read_bytes(&env, ptr, len)?).into_owned(),
+        module_path: None,
+        file: None,
+        line: None,
+    };
+    emit_log(&env.data().wrap_name, &record);
+    Ok(())
+}
+
+fn log_record(env: FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> Result<(), RuntimeError> {
+    let bytes = read_bytes(&env, ptr, len)?;
+    let (record, _) =
+        bincode::decode_from_slice::<LogRecord, _>(&bytes, bincode::config::standard())
+            .map_err(|error| RuntimeError::new(format!("invalid log record: {}", error)))?;
+    emit_log(&env.data().wrap_name, &record);
+    Ok(())
+}
+
+/// Called by the wrap's panic hook right before it traps
+fn report_panic(mut env: FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> Result<(), RuntimeError> {
+    let bytes = read_bytes(&env, ptr, len)?;
+    let (record, _) =
+        bincode::decode_from_slice::<LogRecord, _>(&bytes, bincode::config::standard())
+            .map_err(|error| RuntimeError::new(format!("invalid panic record: {}", error)))?;
+    emit_log(&env.data().wrap_name, &record);
+    env.data_mut().panic = Some(record);
+    Ok(())
+}
+
+fn now(_env: FunctionEnvMut<HostEnv>) -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn random(env: FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> Result<(), RuntimeError> {
+    let mut buffer = vec![0; checked_len(&env, ptr, len)?];
+    rand::thread_rng().fill_bytes(&mut buffer);
+    write_bytes(&env, ptr, &buffer)
+}
+
+fn read_stream(
+    env: FunctionEnvMut<HostEnv>,
+    stream_id: u64,
+    ptr: u64,
+    len: u64,
+) -> Result<u64, RuntimeError> {
+    let mut buffer = vec![0; checked_len(&env, ptr, len.min(STREAM_CHUNK_LIMIT))?];
+    let scope = env.data().scope.as_ref();
+    match scope.and_then(|scope| scope.read_stream(stream_id, &mut buffer)) {
+        Some(read) => {
+            write_bytes(&env, ptr, &buffer[..read])?;
+            Ok(read as u64)
+        }
+        None => Ok(STREAM_ERROR),
+    }
+}
+
+/// Hand bytes to the wrap as a stream of the signal it is handling
+fn open_stream(env: &FunctionEnvMut<HostEnv>, bytes: Vec<u8>) -> u64 {
+    match &env.data().scope {
+        Some(scope) => scope.open_stream(Box::new(Cursor::new(bytes))),
+        None => STREAM_ERROR,
+    }
+}
+
+fn open_reference(env: FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> Result<u64, RuntimeError> {
+    let bytes = read_bytes(&env, ptr, len)?;
+    let reference: DataReference =
+        match bincode::decode_from_slice(&bytes, bincode::config::standard()) {
+            Ok((reference, _)) => reference,
+            Err(_) => return Ok(STREAM_ERROR),
+        };
+    Ok(env
+        .data()
+        .scope
+        .as_ref()
+        .and_then(|scope| scope.open_reference(&reference))
+        .unwrap_or(STREAM_ERROR))
+}
+
+fn close_stream(env: FunctionEnvMut<HostEnv>, stream_id: u64) {
+    if let Some(scope) = &env.data().scope {
+        scope.close_stream(stream_id);
+    }
+}
+
+/// Send a signal to the wraps of a key, returning a stream holding the encoded response
+///
+/// The response is always a `Result<ResponseType, WrapError>`, even when the signal couldn't be
+/// delivered, so wraps can decode it like any response
+fn signal_wrap(
+    env: FunctionEnvMut<HostEnv>,
+    query_id: u64,
+    version: u32,
+    key_ptr: u64,
+    key_len: u64,
+    signal_ptr: u64,
+    signal_len: u64,
+) -> Result<u64, RuntimeError> {
+    let host_env = env.data();
+    let output_signal_bytes = if let Err(error) = host_env.require(Capability::SignalWraps) {
+        Err(error)
+    } else {
+        let key_bytes = read_bytes(&env, key_ptr, key_len)?;
+        let input_signal_bytes = read_bytes(&env, signal_ptr, signal_len)?;
+        let query = SupportedQuery { query_id, version };
+
+        bincode::decode_from_slice::<WrapKey, _>(&key_bytes, bincode::config::standard())
+            .map_err(|error| WrapError::DecodeFailed(format!("{}", error)))
+            .and_then(|(key, _)| {
+                host_env
+                    .router
+                    .signal_raw(host_env.wrap_id.as_ref(), &key, &query, &input_signal_bytes)
+                    .map_err(|error| WrapError::Other(format!("{:?}", error)))
+            })
+    };
+
+    // Errors are encoded the same way whatever the response type
+    let output_signal_bytes = match output_signal_bytes {
+        Ok(output_signal_bytes) => output_signal_bytes,
+        Err(error) => {
+            bincode::encode_to_vec(Err::<(), _>(error), bincode::config::standard())
+                .map_err(|error| RuntimeError::new(format!("{}", error)))?
+        }
+    };
+    Ok(open_stream(&env, output_signal_bytes))
+}
+
+/// Hand a result back to the wrap as a stream, since its size isn't known in advance
+fn respond<T: Encode>(
+    env: &FunctionEnvMut<HostEnv>,
+    result: &StorageResult<T>,
+) -> Result<u64, RuntimeError> {
+    let bytes = bincode::encode_to_vec(result, bincode::config::standard())
+        .map_err(|error| RuntimeError::new(format!("{}", error)))?;
+    Ok(open_stream(env, bytes))
+}
+
+fn read_string(env: &FunctionEnvMut<HostEnv>, ptr: u64, len: u64) -> StorageResult<String> {
+    let bytes = read_bytes(env, ptr, len).map_err(|_| StorageError::InvalidKey)?;
+    String::from_utf8(bytes).map_err(|_| StorageError::InvalidKey)
+}
+
+fn storage(env: &FunctionEnvMut<HostEnv>) -> StorageResult<WrapStorage> {
+    env.data()
+        .require(Capability::Storage)
+        .map_err(|_| StorageError::CapabilityDenied)?;
+    Ok(env.data().storage.clone())
+}
+
+fn storage_get(
+    env: FunctionEnvMut<HostEnv>,
+    key_ptr: u64,
+    key_len: u64,
+) -> Result<u64, RuntimeError> {
+    let result =
+        storage(&env).and_then(|storage| storage.get(&read_string(&env, key_ptr, key_len)?));
+    respond(&env, &result)
+}
+
+fn storage_put(
+    env: FunctionEnvMut<HostEnv>,
+    key_ptr: u64,
+    key_len: u64,
+    value_ptr: u64,
+    value_len: u64,
+) -> Result<u64, RuntimeError> {
+    // The value is only read once the wrap is known to be allowed to store it
+    let checked = storage(&env).and_then(|storage| {
+        let key = read_string(&env, key_ptr, key_len)?;
+        storage.check_quota(&key, value_len)?;
+        Ok((storage, key))
+    });
+    let result = match checked {
+        Ok((storage, key)) => storage.put(&key, read_bytes(&env, value_ptr, value_len)?),
+        Err(error) => Err(error),
+    };
+    respond(&env, &result)
+}
+
+fn storage_delete(
+    env: FunctionEnvMut<HostEnv>,
+    key_ptr: u64,
+    key_len: u64,
+) -> Result<u64, RuntimeError> {
+    let result =
+        storage(&env).and_then(|storage| storage.delete(&read_string(&env, key_ptr, key_len)?));
+    respond(&env, &result)
+}
+
+fn storage_list(
+    env: FunctionEnvMut<HostEnv>,
+    prefix_ptr: u64,
+    prefix_len: u64,
+) -> Result<u64, RuntimeError> {
+    let result =
+        storage(&env).and_then(|storage| storage.list(&read_string(&env, prefix_ptr, prefix_len)?));
+    respond(&env, &result)
+}
+
+/// Register every host function under the versioned host namespace
+pub fn register_host_functions(
+    imports: &mut Imports,
+    store: &mut Store,
+    env: &FunctionEnv<HostEnv>,
+) {
+    imports.define(
+        HOST_MODULE,
+        "log",
+        Function::new_typed_with_env(store, env, log),
+    );
+    imports.define(
+        HOST_MODULE,
+        "log_record",
+        Function::new_typed_with_env(store, env, log_record),
+    );
+    imports.define(
+        HOST_MODULE,
+        "report_panic",
+        Function::new_typed_with_env(store, env, report_panic),
+    );
+    imports.define(
+        HOST_MODULE,
+        "now",
+        Function::new_typed_with_env(store, env, now),
+    );
+    imports.define(
+        HOST_MODULE,
+        "random",
+        Function::new_typed_with_env(store, env, random),
+    );
+    imports.define(
+        HOST_MODULE,
+        "read_stream",
+        Function::new_typed_with_env(store, env, read_stream),
+    );
+    imports.define(
+        HOST_MODULE,
+        "open_reference",
+        Function::new_typed_with_env(store, env, open_reference),
+    );
+    imports.define(
+        HOST_MODULE,
+        "close_stream",
+        Function::new_typed_with_env(store, env, close_stream),
+    );
+    imports.define(
+        HOST_MODULE,
+        "signal_wrap",
+        Function::new_typed_with_env(store, env, signal_wrap),
+    );
+    imports.define(
+        HOST_MODULE,
+        "storage_get",
+        Function::new_typed_with_env(store, env, storage_get),
+    );
+    imports.define(
+        HOST_MODULE,
+        "storage_put",
+        Function::new_typed_with_env(store, env, storage_put),
+    );
+    imports.define(
+        HOST_MODULE,
+        "storage_delete",
+        Function::new_typed_with_env(store, env, storage_delete),
+    );
+    imports.define(
+        HOST_MODULE,
+        "storage_list",
+        Function::new_typed_with_env(store, env, storage_list),
+    );
+}
@@ -0,0 +1,74 @@
+use super::Wraps;
+use bevy::prelude::*;
+use hmny_common::prelude::*;
+use url::Url;
+
+#[derive(Event, Clone, Debug)]
+pub struct WrapLoaded {
+    pub key: WrapKey,
+    pub metadata: WrapMetdata,
+    pub source: Url,
+}
+
+/// Sent instead of [`WrapLoaded`] when a wrap takes the place of another with the same name, such as
+/// a new build of itself
+#[derive(Event, Clone, Debug)]
+pub struct WrapReplaced {
+    pub key: WrapKey,
+    pub metadata: WrapMetdata,
+    pub previous: WrapMetdata,
+    pub source: Url,
+}
+
+#[derive(Event, Clone, Debug)]
+pub struct WrapUnloaded {
+    pub key: WrapKey,
+    pub metadata: WrapMetdata,
+}
+
+/// Sent once when a wrap exceeds its limits and stops accepting signals
+#[derive(Event, Clone, Debug)]
+pub struct WrapFaulted {
+    pub key: WrapKey,
+    pub metadata: WrapMetdata,
+    /// Whether its fault policy unloaded the wrap, in which case a [`WrapUnloaded`] follows
+    pub unloaded: bool,
+}
+
+/// Sent when a wrap was loaded asking for capabilities the user hasn't decided on yet
+///
+/// Until the user decides through [`Wraps::decide_capability`], those capabilities are denied
+#[derive(Event, Clone, Debug)]
+pub struct WrapConsentRequested {
+    pub key: WrapKey,
+    pub metadata: WrapMetdata,
+    pub capabilities: Vec<Capability>,
+}
+
+/// Lifecycle events are queued by [`Wraps`] as it changes, and sent once per frame
+pub(super) enum WrapLifecycleEvent {
+    Loaded(WrapLoaded),
+    Replaced(WrapReplaced),
+    Unloaded(WrapUnloaded),
+    Faulted(WrapFaulted),
+    ConsentRequested(WrapConsentRequested),
+}
+
+pub(super) fn send_lifecycle_events(
+    mut wraps: ResMut<Wraps>,
+    mut loaded: EventWriter<WrapLoaded>,
+    mut replaced: EventWriter<WrapReplaced>,
+    mut unloaded: EventWriter<WrapUnloaded>,
+    mut faulted: EventWriter<WrapFaulted>,
+    mut consent_requested: EventWriter<WrapConsentRequested>,
+) {
+    for event in wraps.take_lifecycle_events() {
+        match event {
+            WrapLifecycleEvent::Loaded(event) => loaded.send(event),
+            WrapLifecycleEvent::Replaced(event) => replaced.send(event),
+            WrapLifecycleEvent::Unloaded(event) => unloaded.send(event),
+            WrapLifecycleEvent::Faulted(event) => faulted.send(event),
+            WrapLifecycleEvent::ConsentRequested(event) => consent_requested.send(event),
+        }
+    }
+}
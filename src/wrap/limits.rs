@@ -0,0 +1,153 @@
+use std::ptr::NonNull;
+use std::sync::Arc;
+use wasmer::{
+    vm::{self, MemoryError, MemoryStyle, TableStyle, VMMemoryDefinition, VMTableDefinition},
+    wasmparser::Operator,
+    BaseTunables, CompilerConfig, Engine, MemoryType, NativeEngineExt, Pages, Store, TableType,
+    Target, Tunables,
+};
+use wasmer_middlewares::Metering;
+
+/// Number of wasm operators a wrap may execute while handling a single signal by default
+///
+/// Fuel counts operators rather than time, so how long it lasts depends on the machine and on the
+/// operators executed. Change it with
+/// [`Wraps::set_default_limits`](super::Wraps::set_default_limits), or per wrap with
+/// [`Wraps::set_limits`](super::Wraps::set_limits)
+pub const DEFAULT_FUEL: u64 = 100_000_000;
+
+/// What happens to a wrap once it exceeds one of its limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Remove the wrap entirely
+    Unload,
+    /// Keep the wrap loaded, but refuse any further signals
+    MarkFaulted,
+    /// Send the signal again up to `attempts` times before marking the wrap as faulted
+    Retry { attempts: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrapLimits {
+    /// Number of wasm operators a wrap may execute while handling a single signal. Defaults to
+    /// [`DEFAULT_FUEL`]
+    pub fuel: u64,
+    /// Maximum size of the wrap's linear memory in 64KiB pages
+    pub max_memory_pages: u32,
+    pub fault_policy: FaultPolicy,
+    /// Consecutive traps, such as panics, after which a wrap is treated like it exceeded its limits.
+    /// 0 never quarantines the wrap
+    pub quarantine_after: u32,
+    /// Combined size in bytes of the keys and values a wrap may keep in its storage
+    pub storage_quota: u64,
+}
+
+impl Default for WrapLimits {
+    fn default() -> Self {
+        Self {
+            fuel: DEFAULT_FUEL,
+            // 256MiB
+            max_memory_pages: 4096,
+            fault_policy: FaultPolicy::MarkFaulted,
+            quarantine_after: 3,
+            // 1MiB
+            storage_quota: 1024 * 1024,
+        }
+    }
+}
+
+impl WrapLimits {
+    /// Create a store whose modules are metered and whose memories can't grow past the limit
+    pub fn create_store(&self) -> Store {
+        // Every operator costs the same for now
+        let metering = Arc::new(Metering::new(self.fuel, |_: &Operator| -> u64 { 1 }));
+        let mut compiler = wasmer::Cranelift::default();
+        compiler.push_middleware(metering);
+
+        let base = BaseTunables::for_target(&Target::default());
+        let tunables = LimitingTunables::new(base, Pages(self.max_memory_pages));
+        let mut engine: Engine = compiler.into();
+        engine.set_tunables(tunables);
+
+        Store::new(engine)
+    }
+}
+
+/// Tunables capping the maximum size of every memory created by a wrap.
+///
+/// Adapted from wasmer's `tunables_limit_memory` example.
+struct LimitingTunables<T: Tunables> {
+    limit: Pages,
+    base: T,
+}
+
+impl<T: Tunables> LimitingTunables<T> {
+    fn new(base: T, limit: Pages) -> Self {
+        Self { limit, base }
+    }
+
+    /// Clamp the maximum of a memory to the limit, since rust wraps never declare one
+    fn adjust_memory(&self, requested: &MemoryType) -> MemoryType {
+        let mut adjusted = requested.clone();
+        adjusted.maximum = Some(match requested.maximum {
+            Some(maximum) if maximum < self.limit => maximum,
+            _ => self.limit,
+        });
+        adjusted
+    }
+
+    fn validate_memory(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.limit {
+            return Err(MemoryError::Generic(
+                "Minimum exceeds the allowed memory limit".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<T: Tunables> Tunables for LimitingTunables<T> {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        let adjusted = self.adjust_memory(memory);
+        self.base.memory_style(&adjusted)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<vm::VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base.create_host_memory(&adjusted, style)
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<vm::VMMemory, MemoryError> {
+        let adjusted = self.adjust_memory(ty);
+        self.validate_memory(&adjusted)?;
+        self.base
+            .create_vm_memory(&adjusted, style, vm_definition_location)
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<vm::VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<vm::VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
@@ -1,17 +1,62 @@
-use bevy::{prelude::*, utils::HashMap};
+use super::consent::{publisher_key, source_key, ConsentError, ConsentStore};
+use super::data::DataStore;
+use super::dispatch::SignalAppExt;
+use super::host::{register_host_functions, HostEnv};
+use super::lifecycle::*;
+use super::limits::{FaultPolicy, WrapLimits};
+use super::module_cache::ModuleCache;
+#[cfg(feature = "native")]
+use super::native::{native_source, NativeRuntime};
+use super::package::{PackageError, TrustStore};
+use super::preferences::WrapPreferences;
+use super::router::WrapRouter;
+use super::search_paths::{user_data_dir, WrapOrigin};
+use super::storage::WrapStorage;
+use super::trap::{TrapKind, WrapTrap};
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+#[cfg(feature = "native")]
+use hmny_common::native::NativeWrap;
 use hmny_common::prelude::*;
 use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, TryLockError};
 use url::Url;
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+
+const STORAGE_DIR: &str = "storage";
+/// Storage of wraps without a verified publisher, keyed by their source
+const UNVERIFIED_DIR: &str = "unverified";
 
 pub struct WrapLoaderPlugin;
 
-struct LoadedWrap {
+/// A wrap instantiated from wasm, with the host functions it imports
+struct WasmRuntime {
     store: wasmer::Store,
     instance: wasmer::Instance,
+    env: wasmer::FunctionEnv<HostEnv>,
     signal: wasmer::TypedFunction<(u64, u64, u64), u64>,
+    alloc: wasmer::TypedFunction<u64, u64>,
+    dealloc: wasmer::TypedFunction<(u64, u64), ()>,
+}
+
+/// What runs a wrap's code
+enum WrapRuntime {
+    Wasm(WasmRuntime),
+    #[cfg(feature = "native")]
+    Native(NativeRuntime),
+}
+
+pub struct LoadedWrap {
+    runtime: WrapRuntime,
     metadata: Option<WrapMetdata>,
+    /// Queries the wrap declared support for, or None if it predates query negotiation
+    supported_queries: Option<Vec<SupportedQuery>>,
+    limits: WrapLimits,
+    faulted: bool,
+    /// Traps other than exceeded limits since the wrap was loaded
+    traps: u32,
 }
 
 impl fmt::Debug for LoadedWrap {
@@ -22,72 +67,275 @@ impl fmt::Debug for LoadedWrap {
 
 #[derive(Debug)]
 pub enum SignalError {
-    MemoryTooSmall(usize),
-    CallFailed(wasmer::RuntimeError),
+    MemoryAccessFailed(wasmer::MemoryAccessError),
+    /// The wrap stopped executing in the middle of the signal
+    Trapped(WrapTrap),
+    /// A previous signal panicked while holding the wrap
+    Poisoned,
+    /// Another wrap signaled a wrap that was busy, most likely further up the same chain of signals
+    WrapBusy,
     WrapError(WrapError),
     DecodeFailed(String),
     EncodeFailed(String),
     WrapDoesNotExist,
+    /// The wrap doesn't support this version of the query, so it was never sent
+    UnsupportedQuery {
+        query_id: u64,
+        version: u32,
+    },
+    /// The wrap previously exceeded its limits, or trapped too often, and no longer accepts signals
+    WrapFaulted,
+    /// The encoded signal could never fit in the wrap's memory. Large payloads should be streamed
+    SignalTooLarge {
+        len: u64,
+        max: u64,
+    },
 }
 
-fn mem_slice_mut(slice: &mut [u8], lower: usize) -> Result<&mut [u8], SignalError> {
-    let max = slice.len();
-    if lower > max {
-        Err(SignalError::MemoryTooSmall(lower - max))
-    } else {
-        Ok(&mut slice[lower..max - lower])
+impl SignalError {
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(self, Self::Trapped(trap) if trap.is_limit_exceeded())
     }
-}
 
-fn mem_slice(slice: &[u8], lower: usize, upper: usize) -> Result<&[u8], SignalError> {
-    assert!(lower <= upper);
-    let max = slice.len();
-    if upper > max {
-        Err(SignalError::MemoryTooSmall(upper - max))
-    } else {
-        Ok(&slice[lower..upper])
+    /// Whether the next candidate should get the signal, because this wrap declined it or never
+    /// received it. Other failures are reported rather than hidden behind another wrap's response
+    pub fn allows_fallback(&self) -> bool {
+        matches!(
+            self,
+            Self::WrapError(_) | Self::UnsupportedQuery { .. } | Self::WrapFaulted
+        )
     }
 }
 
-impl LoadedWrap {
+fn encode_signal<Signal: HarmonySignal>(signal: Signal) -> Result<Vec<u8>, SignalError> {
+    bincode::encode_to_vec(signal, bincode::config::standard())
+        .map_err(|error| SignalError::EncodeFailed(format!("{}", error)))
+}
+
+fn decode_response<Signal: HarmonySignal>(
+    bytes: &[u8],
+) -> Result<Signal::ResponseType, SignalError> {
+    // Output signals are always a Result<ResponseType, WrapError>
+    let (output_signal, _) = bincode::decode_from_slice::<
+        Result<<Signal as HarmonySignal>::ResponseType, WrapError>,
+        _,
+    >(bytes, bincode::config::standard())
+    .map_err(|error| SignalError::DecodeFailed(format!("{}", error)))?;
+
+    output_signal.map_err(SignalError::WrapError)
+}
+
+impl WasmRuntime {
     const MEMORY: &str = "memory";
 
-    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<Self, WrapLoaderError> {
-        // Create a Store.
-        let mut store = wasmer::Store::default();
+    fn new(
+        bytes: &[u8],
+        limits: &WrapLimits,
+        module_cache: &ModuleCache,
+    ) -> Result<Self, WrapLoaderError> {
+        // Create a Store that meters execution and caps memory
+        let mut store = limits.create_store();
 
-        // We then use our store and Wasm bytes to compile a `Module`.
+        // We then use our store and Wasm bytes to compile a `Module`, unless it was compiled before.
         // A `Module` is a compiled WebAssembly module that isn't ready to execute yet.
-        let module = wasmer::Module::new(&store, bytes).map_err(WrapLoaderError::InvalidWasm)?;
+        let module = module_cache
+            .load_or_compile(&store, bytes, limits)
+            .map_err(WrapLoaderError::InvalidWasm)?;
 
         // Initiate shared memory pool
         let memory = wasmer::Memory::new(&mut store, wasmer::MemoryType::new(1, None, false))
-            .expect("wasm memory allocation failed");
-        let import_object = wasmer::imports! {
+            .map_err(WrapLoaderError::MemoryError)?;
+        let mut import_object = wasmer::imports! {
             "env" => {
                 Self::MEMORY => memory,
             },
         };
 
+        // Functions wraps can call back into
+        let env = wasmer::FunctionEnv::new(&mut store, HostEnv::new());
+        register_host_functions(&mut import_object, &mut store, &env);
+
         // We then use the `Module` and the import object to create an `Instance`.
         //
         // An `Instance` is a compiled WebAssembly module that has been set up
         // and is ready to execute.
         let instance = wasmer::Instance::new(&mut store, &module, &import_object)
-            .expect("wasm instantiation failed");
+            .map_err(WrapLoaderError::InstantiationFailed)?;
+
+        // Host functions need access to the wrap's own memory
+        let exported_memory = instance
+            .exports
+            .get_memory(Self::MEMORY)
+            .map_err(WrapLoaderError::MissingExport)?
+            .clone();
+        env.as_mut(&mut store).set_memory(exported_memory);
 
         // Init typed functions
         let signal = instance
             .exports
             .get_typed_function(&store, "signal")
             .map_err(WrapLoaderError::MissingExport)?;
+        let alloc = instance
+            .exports
+            .get_typed_function(&store, "alloc")
+            .map_err(WrapLoaderError::MissingExport)?;
+        let dealloc = instance
+            .exports
+            .get_typed_function(&store, "dealloc")
+            .map_err(WrapLoaderError::MissingExport)?;
 
-        // Load a temporary wrap
-        let mut wrap = LoadedWrap {
+        Ok(Self {
             store,
             instance,
+            env,
             signal,
+            alloc,
+            dealloc,
+        })
+    }
+
+    fn host_env_mut(&mut self) -> &mut HostEnv {
+        self.env.as_mut(&mut self.store)
+    }
+
+    fn get_memory<'a>(&'a self) -> &'a wasmer::Memory {
+        self.instance.exports.get_memory(Self::MEMORY).unwrap()
+    }
+
+    fn get_memory_view(&mut self) -> wasmer::MemoryView {
+        self.get_memory().view(&self.store)
+    }
+
+    /// Figure out why a call failed, and whether it was caused by the wrap exceeding one of its limits
+    fn classify_call_error(
+        &mut self,
+        error: wasmer::RuntimeError,
+        limits: &WrapLimits,
+    ) -> SignalError {
+        let kind = if let MeteringPoints::Exhausted =
+            get_remaining_points(&mut self.store, &self.instance)
+        {
+            Some(TrapKind::FuelExhausted)
+        } else if self.get_memory_view().size() >= wasmer::Pages(limits.max_memory_pages) {
+            // Rust wraps abort when memory can't grow, so a trap with memory at its limit is most likely the cause
+            Some(TrapKind::MemoryLimitReached)
+        } else {
+            None
+        };
+
+        let panic = self.host_env_mut().panic.take();
+        SignalError::Trapped(WrapTrap::new(&error, kind, panic))
+    }
+
+    /// Copy bytes into a buffer allocated by the wrap, returning a pointer to it
+    fn write_buffer(&mut self, bytes: &[u8], limits: &WrapLimits) -> Result<u64, SignalError> {
+        let ptr = self
+            .alloc
+            .call(&mut self.store, bytes.len() as _)
+            .map_err(|error| self.classify_call_error(error, limits))?;
+        self.get_memory_view()
+            .write(ptr, bytes)
+            .map_err(SignalError::MemoryAccessFailed)?;
+        Ok(ptr)
+    }
+
+    /// Copy a buffer out of the wrap's memory
+    fn read_buffer(&mut self, ptr: u64, len: u64) -> Result<Vec<u8>, SignalError> {
+        // The wrap reports the length, so check it before allocating anything
+        let view = self.get_memory_view();
+        if !ptr
+            .checked_add(len)
+            .is_some_and(|end| end <= view.data_size())
+        {
+            return Err(SignalError::MemoryAccessFailed(
+                wasmer::MemoryAccessError::HeapOutOfBounds,
+            ));
+        }
+
+        let mut bytes = vec![0; len as usize];
+        view.read(ptr, &mut bytes)
+            .map_err(SignalError::MemoryAccessFailed)?;
+        Ok(bytes)
+    }
+
+    fn free_buffer(&mut self, ptr: u64, len: u64, limits: &WrapLimits) -> Result<(), SignalError> {
+        self.dealloc
+            .call(&mut self.store, ptr, len)
+            .map_err(|error| self.classify_call_error(error, limits))
+    }
+
+    fn send_raw(
+        &mut self,
+        query_id: u64,
+        input_signal_bytes: &[u8],
+        limits: &WrapLimits,
+    ) -> Result<Vec<u8>, SignalError> {
+        let max_len = limits.max_memory_pages as u64 * wasmer::WASM_PAGE_SIZE as u64;
+        if input_signal_bytes.len() as u64 > max_len {
+            return Err(SignalError::SignalTooLarge {
+                len: input_signal_bytes.len() as u64,
+                max: max_len,
+            });
+        }
+
+        // Every signal gets a fresh fuel budget
+        set_remaining_points(&mut self.store, &self.instance, limits.fuel);
+        self.host_env_mut().panic = None;
+
+        // Copy input signal into a buffer requested from the wrap
+        let input_signal_size = input_signal_bytes.len() as u64;
+        let input_signal_ptr = self.write_buffer(input_signal_bytes, limits)?;
+
+        // Calls the wasm function passing pointer to signal
+        let signal_call_result = self.signal.call(
+            &mut self.store,
+            query_id,
+            input_signal_ptr,
+            input_signal_size,
+        );
+        let signal_call_result =
+            signal_call_result.map_err(|error| self.classify_call_error(error, limits))?;
+
+        // The input buffer is no longer needed
+        self.free_buffer(input_signal_ptr, input_signal_size, limits)?;
+
+        // Copy the output buffer out of wasm memory and hand it back to the wrap
+        let (output_signal_ptr, output_signal_size) = memory::unpack_ptr_len(signal_call_result);
+        let output_signal_bytes = self.read_buffer(output_signal_ptr, output_signal_size)?;
+        self.free_buffer(output_signal_ptr, output_signal_size, limits)?;
+
+        Ok(output_signal_bytes)
+    }
+}
+
+impl LoadedWrap {
+    pub fn from_bytes(
+        bytes: impl AsRef<[u8]>,
+        limits: WrapLimits,
+        module_cache: &ModuleCache,
+    ) -> Result<Self, WrapLoaderError> {
+        let runtime = WasmRuntime::new(bytes.as_ref(), &limits, module_cache)?;
+        Self::from_runtime(WrapRuntime::Wasm(runtime), limits)
+    }
+
+    /// Run a wrap linked into the host rather than loaded from wasm
+    ///
+    /// Native wraps call the std fallbacks of host functions and aren't held to their limits, so
+    /// this is only meant for debugging
+    #[cfg(feature = "native")]
+    pub fn from_native(wrap: NativeWrap, limits: WrapLimits) -> Result<Self, WrapLoaderError> {
+        Self::from_runtime(WrapRuntime::Native(NativeRuntime::new(wrap)), limits)
+    }
+
+    fn from_runtime(runtime: WrapRuntime, limits: WrapLimits) -> Result<Self, WrapLoaderError> {
+        // Load a temporary wrap
+        let mut wrap = LoadedWrap {
+            runtime,
             metadata: None,
+            supported_queries: None,
+            limits,
+            faulted: false,
+            traps: 0,
         };
 
         // Retrieve metadata
@@ -99,101 +347,251 @@ impl LoadedWrap {
                 _ => Err(WrapLoaderError::InvalidMetdata),
             })?;
 
-        // Check that wrap interface version matches own (mismatching versions might lead to deserialization/serialization failure later)
-        if !metadata.interface_version.matches_own() {
+        // Check that wrap interface version is compatible with own (incompatible versions might lead to deserialization/serialization failure later)
+        if !metadata.interface_version.is_compatible_with_own() {
             return Err(WrapLoaderError::UnsupportedInterfaceVersion(
                 metadata.interface_version,
             ));
         }
 
+        // Negotiate which queries can be sent to the wrap
+        wrap.supported_queries = match wrap.send_signal(CommonQuery::AskSupportedQueries) {
+            Ok(CommonResponse::SupportedQueries(queries)) => Some(queries),
+            // Wraps predating negotiation either don't know the query or can't decode it
+            Err(SignalError::WrapError(WrapError::UnsupportedSignal))
+            | Err(SignalError::WrapError(WrapError::DecodeFailed(_))) => None,
+            other => {
+                warn!(
+                    "Could not negotiate queries with {:?}: {:?}",
+                    metadata.name, other
+                );
+                None
+            }
+        };
+
+        wrap.host_env_mut().wrap_name = metadata.name.clone();
         wrap.metadata = Some(metadata);
         Ok(wrap)
     }
 
-    fn get_memory<'a>(&'a self) -> &'a wasmer::Memory {
-        self.instance.exports.get_memory(Self::MEMORY).unwrap()
+    /// Send an already encoded signal, returning the still encoded response
+    pub fn send_raw(
+        &mut self,
+        query_id: u64,
+        input_signal_bytes: &[u8],
+    ) -> Result<Vec<u8>, SignalError> {
+        // Streams the wrap opens while handling the signal are closed once it responds
+        let env = self.host_env_mut();
+        env.scope = Some(env.data.scope(input_signal_bytes));
+
+        let result = match &mut self.runtime {
+            WrapRuntime::Wasm(runtime) => {
+                runtime.send_raw(query_id, input_signal_bytes, &self.limits)
+            }
+            #[cfg(feature = "native")]
+            WrapRuntime::Native(runtime) => runtime.send_raw(query_id, input_signal_bytes),
+        };
+        self.host_env_mut().scope = None;
+        result
     }
 
-    fn get_memory_view(&mut self) -> wasmer::MemoryView {
-        self.get_memory().view(&self.store)
+    /// Whether the wrap runs natively rather than from wasm
+    pub fn is_native(&self) -> bool {
+        match self.runtime {
+            WrapRuntime::Wasm(_) => false,
+            #[cfg(feature = "native")]
+            WrapRuntime::Native(_) => true,
+        }
+    }
+
+    /// Whether the wrap can handle this version of the query
+    pub fn supports<Signal: HarmonySignal>(&self) -> bool {
+        self.supports_query(&SupportedQuery::of::<Signal>())
+    }
+
+    /// Like [`Self::supports`], for queries only known by their id and version
+    pub fn supports_query(&self, query: &SupportedQuery) -> bool {
+        match &self.supported_queries {
+            Some(queries) => queries.contains(query),
+            // Nothing is known about older wraps, so assume they do
+            None => true,
+        }
     }
 
     pub fn send_signal<Signal: HarmonySignal>(
         &mut self,
         input_signal: Signal,
     ) -> Result<Signal::ResponseType, SignalError> {
-        let config = bincode::config::standard();
-        let view = self.get_memory_view();
-        let memory_slice = unsafe { view.data_unchecked_mut() };
-
-        // Serialize input signal into wasm memory starting at 0
-        let input_signal_ptr = 0;
-        let input_signal_slice = mem_slice_mut(memory_slice, input_signal_ptr)?;
-        let input_signal_size =
-            bincode::encode_into_slice(input_signal, input_signal_slice, config)
-                .map_err(|error| SignalError::EncodeFailed(format!("{}", error)))?;
+        let input_signal_bytes = encode_signal(input_signal)?;
+        let output_signal_bytes = self.send_raw(Signal::QUERY_ID, &input_signal_bytes)?;
+        decode_response::<Signal>(&output_signal_bytes)
+    }
 
-        // Calls the wasm function passing pointer to signal
-        let signal_call_result = self
-            .signal
-            .call(
-                &mut self.store,
-                Signal::QUERY_ID,
-                input_signal_ptr as _,
-                input_signal_size as _,
-            )
-            .map_err(SignalError::CallFailed)?;
+    /// Send a signal, applying the wrap's fault policy if it exceeds its limits
+    pub fn send_signal_with_policy<Signal: HarmonySignal>(
+        &mut self,
+        input_signal: Signal,
+    ) -> Result<Signal::ResponseType, SignalError> {
+        let input_signal_bytes = encode_signal(input_signal)?;
+        self.send_encoded_with_policy::<Signal>(&input_signal_bytes)
+    }
 
-        // Since self was borrowed to call the signal, we need to get a new view
-        let view = self.get_memory_view();
-        let memory_slice = unsafe { view.data_unchecked_mut() };
+    fn send_encoded_with_policy<Signal: HarmonySignal>(
+        &mut self,
+        input_signal_bytes: &[u8],
+    ) -> Result<Signal::ResponseType, SignalError> {
+        let query = SupportedQuery::of::<Signal>();
+        let output_signal_bytes = self.send_raw_with_policy(&query, input_signal_bytes)?;
+        decode_response::<Signal>(&output_signal_bytes)
+    }
 
-        // Retrieve output buffer (slice of memory)
-        let output_signal_slice = {
-            // Break out length and pointer from u64
-            let length = (signal_call_result & 0xFFFFFFFF) as usize;
-            let lower = (signal_call_result >> 32) as usize;
+    /// Send an already encoded signal, applying the wrap's fault policy if it exceeds its limits
+    pub fn send_raw_with_policy(
+        &mut self,
+        query: &SupportedQuery,
+        input_signal_bytes: &[u8],
+    ) -> Result<Vec<u8>, SignalError> {
+        if self.faulted {
+            return Err(SignalError::WrapFaulted);
+        }
+        if !self.supports_query(query) {
+            return Err(SignalError::UnsupportedQuery {
+                query_id: query.query_id,
+                version: query.version,
+            });
+        }
 
-            // Raw output buffer
-            mem_slice(memory_slice, lower, lower + length)?
+        let mut retries = match self.limits.fault_policy {
+            FaultPolicy::Retry { attempts } => attempts,
+            _ => 0,
         };
 
-        // Retrieve output signal (always a Result<ResponseType, WrapError>)
-        let (output_signal, _) = bincode::decode_from_slice::<
-            Result<<Signal as HarmonySignal>::ResponseType, WrapError>,
-            _,
-        >(output_signal_slice, config)
-        .map_err(|error| SignalError::DecodeFailed(format!("{}", error)))?;
-
-        output_signal.map_err(SignalError::WrapError)
+        loop {
+            match self.send_raw(query.query_id, input_signal_bytes) {
+                Err(error) if error.is_limit_exceeded() && retries > 0 => {
+                    warn!("{:?} exceeded its limits ({:?}), retrying", self, error);
+                    retries -= 1;
+                }
+                Err(error) if error.is_limit_exceeded() => {
+                    error!("{:?} exceeded its limits: {:?}", self, error);
+                    self.faulted = true;
+                    return Err(error);
+                }
+                Err(SignalError::Trapped(trap)) => {
+                    error!("{:?} trapped: {}", self, trap);
+                    self.traps += 1;
+                    let quarantine_after = self.limits.quarantine_after;
+                    if quarantine_after > 0 && self.traps >= quarantine_after {
+                        error!("Quarantining {:?} after {} traps", self, self.traps);
+                        self.faulted = true;
+                    }
+                    return Err(SignalError::Trapped(trap));
+                }
+                Ok(output_signal_bytes) => {
+                    // Only consecutive traps count towards quarantine
+                    self.traps = 0;
+                    return Ok(output_signal_bytes);
+                }
+                result => return result,
+            }
+        }
     }
 
     pub fn get_metadata(&self) -> &WrapMetdata {
         self.metadata.as_ref().unwrap()
     }
+
+    /// State shared with the wrap's host functions, such as its granted capabilities
+    pub fn host_env_mut(&mut self) -> &mut HostEnv {
+        match &mut self.runtime {
+            WrapRuntime::Wasm(runtime) => runtime.host_env_mut(),
+            #[cfg(feature = "native")]
+            WrapRuntime::Native(runtime) => &mut runtime.env,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum WrapLoaderError {
     FileNotFound,
+    InvalidPath(PathBuf),
+    NotLoaded,
+    /// The same wrap was already loaded from somewhere with a higher precedence
+    Shadowed(WrapId),
     InvalidWasm(wasmer::CompileError),
+    MemoryError(wasmer::MemoryError),
+    InstantiationFailed(wasmer::InstantiationError),
     SignalError(SignalError),
     MissingExport(wasmer::ExportError),
+    PackageError(PackageError),
     InvalidMetdata,
     UnsupportedInterfaceVersion(InterfaceVersion),
 }
 
-#[derive(Debug, Hash, PartialEq, Eq, Clone)]
-pub enum WrapKey {
-    HomeScreen,
-    Mimetype(String),
-    Other(WrapType, String),
+/// Sent whenever a wrap file could not be loaded
+#[derive(Event, Debug)]
+pub struct WrapLoadFailed {
+    pub path: PathBuf,
+    pub error: WrapLoaderError,
+}
+
+/// Identifies a loaded wrap, since wraps of different publishers may share the same name
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WrapId {
+    /// The verified publisher, or the one an unverified wrap claims marked as such
+    pub publisher: String,
+    pub name: String,
+}
+
+impl WrapId {
+    /// Id of a loaded wrap, such as the one of a [`WrapLoaded`] event
+    pub fn of(metadata: &WrapMetdata) -> Self {
+        Self {
+            publisher: publisher_key(metadata),
+            name: metadata.name.clone(),
+        }
+    }
+}
+
+struct WrapEntry {
+    key: WrapKey,
+    wrap: Arc<Mutex<LoadedWrap>>,
+    /// Breaks ties between equally preferred wraps, earliest loaded first
+    load_order: u64,
+    origin: WrapOrigin,
+    /// Where the wrap was loaded from
+    source: Url,
+    metadata: WrapMetdata,
+    /// Whether a [`WrapFaulted`] event was already sent for this wrap
+    fault_reported: bool,
 }
 
 #[derive(Resource)]
 pub struct Wraps {
-    source_map: HashMap<Url, WrapKey>,
-    loaded: HashMap<WrapKey, LoadedWrap>,
+    /// Wrap loaded from each source
+    source_map: HashMap<Url, WrapId>,
+    /// Several wraps may share the same key
+    loaded: HashMap<WrapId, WrapEntry>,
+    next_load_order: u64,
+    default_limits: WrapLimits,
+    /// Limits overriding the defaults for specific wraps, by wrap name
+    limits: HashMap<String, WrapLimits>,
+    pub trust_store: TrustStore,
+    pub preferences: WrapPreferences,
+    pub consent: ConsentStore,
+    /// Shared by every wrap, so payloads can be created before knowing which wrap will read them.
+    /// Wraps can only read the streams sent in the signal they are handling
+    pub data: DataStore,
+    /// Routes signals wraps send each other
+    router: WrapRouter,
+    /// Compiled modules of wraps loaded before
+    pub module_cache: ModuleCache,
+    /// Where wraps keep their storage, or None to forget it once they are unloaded
+    storage_dir: Option<PathBuf>,
+    /// Sent as Bevy events at the end of the frame
+    lifecycle_events: Vec<WrapLifecycleEvent>,
+    /// Wraps whose capabilities were decided while they were busy
+    pending_capabilities: HashSet<WrapId>,
 }
 
 impl Default for Wraps {
@@ -201,25 +599,145 @@ impl Default for Wraps {
         Self {
             source_map: HashMap::new(),
             loaded: HashMap::new(),
+            next_load_order: 0,
+            default_limits: WrapLimits::default(),
+            limits: HashMap::new(),
+            trust_store: TrustStore::default(),
+            preferences: WrapPreferences::default(),
+            consent: ConsentStore::default(),
+            data: DataStore::default(),
+            router: WrapRouter::default(),
+            module_cache: ModuleCache::default(),
+            storage_dir: user_data_dir().map(|dir| dir.join(STORAGE_DIR)),
+            lifecycle_events: Vec::new(),
+            pending_capabilities: HashSet::new(),
         }
     }
 }
 
-fn path_to_url<P: AsRef<Path>>(path: P) -> Url {
-    let absolute = fs::canonicalize(path).expect("path could not be canonicalized");
-    Url::from_file_path(absolute).expect("path could not be converted to url")
+pub(super) fn path_to_url<P: AsRef<Path>>(path: P) -> Result<Url, WrapLoaderError> {
+    let path = path.as_ref();
+    // Removed files can't be canonicalized, but still need to be unloaded
+    let absolute = fs::canonicalize(path)
+        .or_else(|_| std::env::current_dir().map(|dir| dir.join(path)))
+        .map_err(|_| WrapLoaderError::InvalidPath(path.into()))?;
+    Url::from_file_path(absolute).map_err(|_| WrapLoaderError::InvalidPath(path.into()))
 }
 
 impl Wraps {
-    pub fn load_from_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WrapLoaderError> {
+    pub fn load_from_path<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        origin: WrapOrigin,
+    ) -> Result<(), WrapLoaderError> {
         let file = fs::read(&path).map_err(|_| WrapLoaderError::FileNotFound)?;
-        self.load(file, path_to_url(path))
+        self.load(file, path_to_url(path)?, origin)
     }
 
-    pub fn load(&mut self, bytes: impl AsRef<[u8]>, source: Url) -> Result<(), WrapLoaderError> {
-        let mut wrap = LoadedWrap::from_bytes(bytes)?;
+    pub fn load(
+        &mut self,
+        bytes: impl AsRef<[u8]>,
+        source: Url,
+        origin: WrapOrigin,
+    ) -> Result<(), WrapLoaderError> {
+        // Signatures are checked before anything gets instantiated
+        let (wasm, verified_publisher) = self
+            .trust_store
+            .open(bytes.as_ref())
+            .map_err(WrapLoaderError::PackageError)?;
+        let mut wrap =
+            LoadedWrap::from_bytes(&wasm, self.default_limits.clone(), &self.module_cache)?;
+
+        // A wrap's name is only known once loaded, so apply any limits specific to it now
+        if let Some(limits) = self.limits.get(&wrap.get_metadata().name) {
+            if limits.max_memory_pages != wrap.limits.max_memory_pages {
+                // The memory limit is fixed when instantiating
+                wrap = LoadedWrap::from_bytes(&wasm, limits.clone(), &self.module_cache)?;
+            } else {
+                wrap.limits = limits.clone();
+            }
+        }
+
+        self.insert(wrap, verified_publisher, source, origin)
+    }
+
+    /// Load a wrap linked into the host, taking precedence over any wasm build of it
+    ///
+    /// See [`LoadedWrap::from_native`]
+    #[cfg(feature = "native")]
+    pub fn load_native(&mut self, native: NativeWrap) -> Result<(), WrapLoaderError> {
+        let source = native_source(&native);
+        let mut wrap = LoadedWrap::from_native(native, self.default_limits.clone())?;
+        if let Some(limits) = self.limits.get(&wrap.get_metadata().name) {
+            wrap.limits = limits.clone();
+        }
+
+        // Native wraps are never signed
+        self.insert(wrap, None, source, WrapOrigin::Native)
+    }
+
+    /// The same wrap found somewhere with a higher precedence wins, unless reloaded from the same source
+    fn check_shadowed(
+        &self,
+        id: &WrapId,
+        source: &Url,
+        origin: WrapOrigin,
+    ) -> Result<(), WrapLoaderError> {
+        if let Some(existing) = self.loaded.get(id) {
+            let reloaded = self
+                .source_map
+                .get(source)
+                .is_some_and(|source| source == id);
+            if existing.origin > origin && !reloaded {
+                return Err(WrapLoaderError::Shadowed(id.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a freshly instantiated wrap, replacing any wrap with the same id
+    fn insert(
+        &mut self,
+        mut wrap: LoadedWrap,
+        verified_publisher: Option<Publisher>,
+        source: Url,
+        origin: WrapOrigin,
+    ) -> Result<(), WrapLoaderError> {
+        let env = wrap.host_env_mut();
+        env.data = self.data.clone();
+        env.router = self.router.clone();
+
+        // Never trust the publisher a wrap claims for itself
+        if let Some(metadata) = wrap.metadata.as_mut() {
+            metadata.verified_publisher = verified_publisher;
+        }
+        let id = WrapId::of(wrap.get_metadata());
+        self.check_shadowed(&id, &source, origin)?;
         info!("Successfully loaded wrap {:?}", wrap);
 
+        // Unverified wraps could claim any publisher, so their storage is keyed by where they were
+        // loaded from instead, and can't be shared with other wraps
+        let metadata = wrap.get_metadata();
+        let quota = wrap.limits.storage_quota;
+        let storage = match (&self.storage_dir, &metadata.verified_publisher) {
+            (Some(dir), Some(publisher)) => {
+                WrapStorage::open(dir, publisher.name(), &metadata.name, quota)
+            }
+            (Some(dir), None) => {
+                let source_key = source_key(&source);
+                WrapStorage::open(dir.join(UNVERIFIED_DIR), &source_key, &metadata.name, quota)
+            }
+            (None, _) => WrapStorage::in_memory(quota),
+        };
+
+        // Only capabilities the user approved are granted, the rest wait for their decision
+        let granted = self.consent.granted(metadata, &source);
+        let env = wrap.host_env_mut();
+        env.wrap_id = Some(id.clone());
+        env.capabilities = granted;
+        env.storage = storage;
+        let undecided = self.consent.undecided(wrap.get_metadata(), &source);
+
         // Send a test ping signal
         let signal = CommonQuery::Ping {
             message: "Harmony core".into(),
@@ -235,55 +753,376 @@ impl Wraps {
             Err(error) => warn!("Error while pinging {:?}", error),
         }
 
-        // Load into hashmap, replacing any existing wrap
+        // Load into hashmap, replacing any existing wrap with the same id while keeping its rank
         let key = Self::get_wrap_key(&wrap);
-        self.loaded.insert(key.clone(), wrap);
-        self.source_map.insert(source, key);
+        let metadata = wrap.get_metadata().clone();
+        let previous = self.loaded.get(&id).map(|previous| {
+            Self::carry_state_over(&previous.wrap, &mut wrap);
+            previous.metadata.clone()
+        });
+        // The source may have been rebuilt under a different name
+        if let Some(previous_id) = self.source_map.get(&source).filter(|other| **other != id) {
+            let previous_id = previous_id.clone();
+            self.remove_entry(&previous_id);
+        }
+        let load_order = match self.loaded.get(&id) {
+            Some(entry) => entry.load_order,
+            None => {
+                self.next_load_order += 1;
+                self.next_load_order
+            }
+        };
+        self.loaded.insert(
+            id.clone(),
+            WrapEntry {
+                key: key.clone(),
+                wrap: Arc::new(Mutex::new(wrap)),
+                load_order,
+                origin,
+                source: source.clone(),
+                metadata: metadata.clone(),
+                fault_reported: false,
+            },
+        );
+        self.source_map.retain(|_, source_id| *source_id != id);
+        self.source_map.insert(source.clone(), id.clone());
+
+        self.lifecycle_events.push(match previous {
+            Some(previous) => WrapLifecycleEvent::Replaced(WrapReplaced {
+                key: key.clone(),
+                metadata: metadata.clone(),
+                previous,
+                source,
+            }),
+            None => WrapLifecycleEvent::Loaded(WrapLoaded {
+                key: key.clone(),
+                metadata: metadata.clone(),
+                source,
+            }),
+        });
+        if !undecided.is_empty() {
+            info!("{:?} is asking for {:?}", id, undecided);
+            self.lifecycle_events
+                .push(WrapLifecycleEvent::ConsentRequested(WrapConsentRequested {
+                    key,
+                    metadata,
+                    capabilities: undecided,
+                }));
+        }
 
         Ok(())
     }
 
+    /// Hand the state of a wrap being replaced over to its replacement, if both support snapshots
+    fn carry_state_over(previous: &Mutex<LoadedWrap>, wrap: &mut LoadedWrap) {
+        // Never wait on the main thread for a signal the previous wrap is still handling
+        let snapshot = match previous.try_lock() {
+            Ok(mut previous) => previous.send_signal_with_policy(CommonQuery::Snapshot),
+            Err(TryLockError::WouldBlock) => Err(SignalError::WrapBusy),
+            Err(TryLockError::Poisoned(_)) => Err(SignalError::Poisoned),
+        };
+
+        match snapshot {
+            Ok(CommonResponse::Snapshot { state }) => {
+                match wrap.send_signal(CommonQuery::Restore { state }) {
+                    Ok(_) => info!("Restored state of {:?}", wrap),
+                    Err(error) => warn!("Could not restore state of {:?}: {:?}", wrap, error),
+                }
+            }
+            // Snapshots are optional, and wraps predating them can't decode the query
+            Err(SignalError::WrapError(WrapError::UnsupportedSignal))
+            | Err(SignalError::WrapError(WrapError::DecodeFailed(_))) => {}
+            other => warn!("Could not snapshot state of {:?}: {:?}", wrap, other),
+        }
+    }
+
+    /// Id and key of the wrap loaded from a file, if any
+    pub fn get_loaded_from_path<P: AsRef<Path>>(&self, path: P) -> Option<(&WrapId, &WrapKey)> {
+        let id = self.source_map.get(&path_to_url(path).ok()?)?;
+        self.loaded.get(id).map(|entry| (id, &entry.key))
+    }
+
     fn get_wrap_key(wrap: &LoadedWrap) -> WrapKey {
         let WrapMetdata {
             wrap_type, name, ..
         } = wrap.get_metadata();
         match wrap_type {
-            // Only one homescreen is loaded at a time
+            // Homescreens and mimetype wraps compete for their key, see WrapPreferences
             WrapType::HomeScreen => WrapKey::HomeScreen,
-            // One wrap per mimetype
             WrapType::Mimetype(mime_type) => WrapKey::Mimetype(mime_type.clone()),
             _ => WrapKey::Other(wrap_type.clone(), name.into()),
         }
     }
 
     pub fn unload_from_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(), WrapLoaderError> {
-        self.unload(&path_to_url(path))
+        self.unload(&path_to_url(path)?)
     }
 
     pub fn unload(&mut self, source: &Url) -> Result<(), WrapLoaderError> {
-        self.source_map
-            .remove(source)
-            .and_then(|key| self.loaded.remove(&key))
-            .ok_or(WrapLoaderError::FileNotFound)?;
+        let id = self
+            .source_map
+            .get(source)
+            .cloned()
+            .ok_or(WrapLoaderError::NotLoaded)?;
+        self.remove_entry(&id);
+
+        Ok(())
+    }
+
+    /// Remove a wrap, along with its sources
+    fn remove_entry(&mut self, id: &WrapId) -> Option<WrapEntry> {
+        let entry = self.loaded.remove(id)?;
+        self.source_map.retain(|_, source_id| source_id != id);
+        self.lifecycle_events
+            .push(WrapLifecycleEvent::Unloaded(WrapUnloaded {
+                key: entry.key.clone(),
+                metadata: entry.metadata.clone(),
+            }));
+        Some(entry)
+    }
+
+    /// Share the loaded wraps and preferences with the router, which can't access this resource
+    fn update_router(&self) {
+        self.router.update(
+            self.loaded
+                .iter()
+                .map(|(id, entry)| (id, &entry.key, entry.load_order, &entry.wrap)),
+            &self.preferences,
+        );
+    }
+
+    pub(super) fn has_lifecycle_events(&self) -> bool {
+        !self.lifecycle_events.is_empty()
+    }
+
+    pub(super) fn take_lifecycle_events(&mut self) -> Vec<WrapLifecycleEvent> {
+        std::mem::take(&mut self.lifecycle_events)
+    }
 
+    /// Remember the user's decision on a capability a loaded wrap asked for, and apply it right away
+    pub fn decide_capability(
+        &mut self,
+        id: &WrapId,
+        capability: Capability,
+        granted: bool,
+    ) -> Result<(), ConsentError> {
+        let entry = self
+            .loaded
+            .get(id)
+            .ok_or_else(|| ConsentError::WrapNotLoaded(id.name.clone()))?;
+        self.consent
+            .decide(&entry.metadata, &entry.source, capability, granted)?;
+
+        self.pending_capabilities.insert(id.clone());
+        self.apply_pending_capabilities();
         Ok(())
     }
 
+    fn has_pending_capabilities(&self) -> bool {
+        !self.pending_capabilities.is_empty()
+    }
+
+    /// Apply decided capabilities to their wraps, unless they are busy handling a signal
+    fn apply_pending_capabilities(&mut self) {
+        let (loaded, consent) = (&self.loaded, &self.consent);
+        self.pending_capabilities.retain(|id| {
+            let Some(entry) = loaded.get(id) else {
+                return false;
+            };
+            match entry.wrap.try_lock() {
+                Ok(mut wrap) => {
+                    wrap.host_env_mut().capabilities =
+                        consent.granted(&entry.metadata, &entry.source);
+                    false
+                }
+                Err(TryLockError::WouldBlock) => true,
+                Err(TryLockError::Poisoned(_)) => {
+                    warn!("Could not update capabilities of poisoned wrap {:?}", id);
+                    false
+                }
+            }
+        });
+    }
+
+    /// Change where wraps loaded from now on keep their storage
+    pub fn set_storage_dir(&mut self, dir: Option<PathBuf>) {
+        self.storage_dir = dir;
+    }
+
+    /// Delete cached modules that can't be used with the current limits
+    pub fn prune_module_cache(&self) {
+        let mut limits: Vec<&WrapLimits> = self.limits.values().collect();
+        limits.push(&self.default_limits);
+        self.module_cache.prune(&limits);
+    }
+
+    pub fn set_default_limits(&mut self, limits: WrapLimits) {
+        self.default_limits = limits;
+    }
+
+    /// Override the limits of a wrap by name. Takes effect the next time the wrap is loaded
+    pub fn set_limits(&mut self, name: impl Into<String>, limits: WrapLimits) {
+        self.limits.insert(name.into(), limits);
+    }
+
+    /// Wraps able to handle a key, from most to least preferred
+    fn ranked(&self, key: &WrapKey) -> Vec<(&WrapId, &WrapEntry)> {
+        self.preferences.rank_candidates(
+            key,
+            self.loaded
+                .iter()
+                .map(|(id, entry)| (id, &entry.key, entry.load_order, (id, entry))),
+        )
+    }
+
+    /// Names of the wraps loaded for a key, from most to least preferred
+    pub fn get_candidate_names(&self, key: &WrapKey) -> Vec<String> {
+        self.ranked(key)
+            .into_iter()
+            .map(|(id, _)| id.name.clone())
+            .collect()
+    }
+
+    /// Get handles to the wraps loaded for a key, from most to least preferred, that can be moved off of the main thread
+    pub fn get_candidates(&self, key: &WrapKey) -> Vec<Arc<Mutex<LoadedWrap>>> {
+        self.ranked(key)
+            .into_iter()
+            .map(|(_, entry)| entry.wrap.clone())
+            .collect()
+    }
+
+    /// Report wraps of a key that have faulted since the last call, applying the unload fault policy
+    ///
+    /// Wraps busy handling a signal on another thread are checked on a later call
+    pub fn handle_faults(&mut self, key: &WrapKey) {
+        let mut faulted = Vec::new();
+        for (id, entry) in self.ranked(key) {
+            if entry.fault_reported {
+                continue;
+            }
+            let unload = match entry.wrap.try_lock() {
+                Ok(wrap) if wrap.faulted => wrap.limits.fault_policy == FaultPolicy::Unload,
+                Ok(_) | Err(TryLockError::WouldBlock) => continue,
+                // A signal panicked while holding the wrap, so it can't be trusted anymore
+                Err(TryLockError::Poisoned(_)) => true,
+            };
+            faulted.push((id.clone(), unload));
+        }
+
+        for (id, unload) in faulted {
+            let Some(entry) = self.loaded.get_mut(&id) else {
+                continue;
+            };
+            entry.fault_reported = true;
+            self.lifecycle_events
+                .push(WrapLifecycleEvent::Faulted(WrapFaulted {
+                    key: entry.key.clone(),
+                    metadata: entry.metadata.clone(),
+                    unloaded: unload,
+                }));
+
+            if unload {
+                warn!("Unloading faulted wrap {:?}", id);
+                self.remove_entry(&id);
+            }
+        }
+    }
+
+    /// Send a signal to the wraps of a key, blocking until one of them responds
+    ///
+    /// Prefer sending a [`SignalRequest`](super::SignalRequest) from systems, which won't stall the frame
     pub fn signal<Signal: HarmonySignal>(
         &mut self,
         key: WrapKey,
         signal: Signal,
     ) -> Result<Signal::ResponseType, SignalError> {
-        let mut return_value = Err(SignalError::WrapDoesNotExist);
-        self.loaded.entry(key).and_modify(|wrap| {
-            return_value = wrap.send_signal(signal);
-        });
+        let return_value = signal_candidates(&self.data, &self.get_candidates(&key), signal);
+        self.handle_faults(&key);
         return_value
     }
 }
 
+/// Send a signal to the most preferred wrap, falling back to the next candidate whenever one declines
+/// it, see [`SignalError::allows_fallback`]
+///
+/// Waits for any signal a wrap is already handling on another thread
+pub fn signal_candidates<Signal: HarmonySignal>(
+    data: &DataStore,
+    candidates: &[Arc<Mutex<LoadedWrap>>],
+    signal: Signal,
+) -> Result<Signal::ResponseType, SignalError> {
+    signal_candidates_named(data, candidates, signal).map(|(_, response)| response)
+}
+
+/// Like [`signal_candidates`], also returning the name of the wrap that responded
+pub fn signal_candidates_named<Signal: HarmonySignal>(
+    data: &DataStore,
+    candidates: &[Arc<Mutex<LoadedWrap>>],
+    signal: Signal,
+) -> Result<(String, Signal::ResponseType), SignalError> {
+    let input_signal_bytes = encode_signal(signal)?;
+    // Streams sent in the signal are kept until every candidate had the chance to read them
+    let _scope = data.scope(&input_signal_bytes);
+    let mut last_error = SignalError::WrapDoesNotExist;
+
+    for wrap in candidates {
+        let result = wrap
+            .lock()
+            .map_err(|_| SignalError::Poisoned)
+            .and_then(|mut wrap| {
+                let response = wrap.send_encoded_with_policy::<Signal>(&input_signal_bytes)?;
+                Ok((wrap.get_metadata().name.clone(), response))
+            });
+        match result {
+            Err(error) if error.allows_fallback() => {
+                debug!(
+                    "Wrap failed to handle signal, trying the next one: {:?}",
+                    error
+                );
+                last_error = error;
+            }
+            result => return result,
+        }
+    }
+
+    Err(last_error)
+}
+
+fn prune_module_cache_system(wraps: Res<Wraps>) {
+    wraps.prune_module_cache();
+}
+
+fn apply_pending_capabilities_system(mut wraps: ResMut<Wraps>) {
+    wraps.apply_pending_capabilities();
+}
+
+fn update_router_system(wraps: Res<Wraps>) {
+    if wraps.is_changed() {
+        wraps.update_router();
+    }
+}
+
 impl Plugin for WrapLoaderPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Wraps>();
+        app.init_resource::<Wraps>()
+            .add_event::<WrapLoadFailed>()
+            .add_event::<WrapLoaded>()
+            .add_event::<WrapReplaced>()
+            .add_event::<WrapUnloaded>()
+            .add_event::<WrapFaulted>()
+            .add_event::<WrapConsentRequested>()
+            .add_systems(PostStartup, prune_module_cache_system)
+            .add_systems(
+                Last,
+                (
+                    // Only run when there is something to do, since borrowing the wraps mutably
+                    // marks them as changed, which would update the router every frame
+                    apply_pending_capabilities_system
+                        .run_if(|wraps: Res<Wraps>| wraps.has_pending_capabilities()),
+                    send_lifecycle_events.run_if(|wraps: Res<Wraps>| wraps.has_lifecycle_events()),
+                    update_router_system,
+                ),
+            )
+            .add_signals();
     }
 }
@@ -1,14 +1,67 @@
 use bevy::prelude::*;
 
+mod client;
+pub use client::*;
+mod consent;
+pub use consent::*;
+mod data;
+pub use data::*;
+mod diagnostics;
+pub use diagnostics::*;
+mod dispatch;
+pub use dispatch::*;
 mod file_watcher;
 pub use file_watcher::*;
+mod host;
+pub use host::*;
+mod lifecycle;
+pub use lifecycle::*;
+mod limits;
+pub use limits::*;
 mod loader;
 pub use loader::*;
+mod module_cache;
+pub use module_cache::*;
+#[cfg(feature = "native")]
+mod native;
+#[cfg(feature = "native")]
+pub use native::*;
+mod package;
+pub use package::*;
+mod preferences;
+pub use preferences::*;
+mod registry;
+pub use registry::*;
+mod router;
+pub use router::*;
+mod search_paths;
+pub use search_paths::*;
+mod storage;
+pub use storage::*;
+mod trap;
+pub use trap::*;
+
+/// An empty directory of its own for every test, named after it
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("hmny-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
 
 pub struct WrapPlugin;
 
 impl Plugin for WrapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((WrapFileWatcherPlugin, WrapLoaderPlugin));
+        app.add_plugins((
+            WrapDiagnosticsPlugin,
+            WrapFileWatcherPlugin,
+            WrapLoaderPlugin,
+            WrapRegistryPlugin,
+        ));
+
+        #[cfg(feature = "native")]
+        app.add_plugins(WrapNativePlugin);
     }
 }
@@ -0,0 +1,270 @@
+use super::search_paths::user_cache_dir;
+use super::WrapLimits;
+use bevy::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use wasmer::{CompileError, Module, Store, Target};
+
+const MODULE_CACHE_DIR: &str = "modules";
+const MODULE_EXTENSION: &str = "bin";
+/// Modules not loaded for this long are deleted when the cache is pruned
+const MAX_MODULE_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// Once modules take more space than this, the least recently loaded are deleted
+const MAX_CACHE_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// Compiled wrap modules saved to disk, so wraps aren't compiled again on every launch and reload
+///
+/// Modules are keyed by a hash of their bytes, inside of a directory keyed by everything else that
+/// affects the compiled code: the wasmer version, the host's CPU features and the wrap's limits,
+/// which are compiled in by the metering middleware. Each file starts with a checksum of the
+/// serialized module, so files corrupted on disk are compiled again instead of loaded. The checksum
+/// can't detect tampering, since anyone able to write the file can also rewrite it
+pub struct ModuleCache {
+    dir: Option<PathBuf>,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        Self::new(user_cache_dir().map(|dir| dir.join(MODULE_CACHE_DIR)))
+    }
+}
+
+fn hash(bytes: impl AsRef<[u8]>) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Read a cached file, returning the serialized module only if it matches its checksum
+fn read_verified(path: &Path) -> std::io::Result<Option<Vec<u8>>> {
+    let mut bytes = fs::read(path)?;
+    let checksum_len = Sha256::output_size();
+    if bytes.len() < checksum_len
+        || bytes[..checksum_len] != Sha256::digest(&bytes[checksum_len..])[..]
+    {
+        return Ok(None);
+    }
+    bytes.drain(..checksum_len);
+    Ok(Some(bytes))
+}
+
+/// Write a serialized module prefixed by its checksum
+fn write_verified(path: &Path, serialized: &[u8]) -> std::io::Result<()> {
+    let mut bytes = Sha256::digest(serialized).to_vec();
+    bytes.extend_from_slice(serialized);
+    fs::write(path, bytes)
+}
+
+/// Mark a module as recently loaded, so it is the last to be pruned
+fn touch(path: &Path) -> std::io::Result<()> {
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+/// Delete modules that haven't been loaded for `max_age`, then the least recently loaded ones
+/// until all of them fit in `max_size`
+fn evict(dirs: &[PathBuf], max_age: Duration, max_size: u64) {
+    let mut modules = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == MODULE_EXTENSION)
+            {
+                if let Ok(metadata) = entry.metadata() {
+                    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    modules.push((path, metadata.len(), modified));
+                }
+            }
+        }
+    }
+
+    // Most recently loaded first
+    modules.sort_by(|a, b| b.2.cmp(&a.2));
+    let now = SystemTime::now();
+    let mut size = 0;
+    for (path, len, modified) in modules {
+        size += len;
+        let expired = now.duration_since(modified).is_ok_and(|age| age > max_age);
+        if expired || size > max_size {
+            if let Err(error) = fs::remove_file(&path) {
+                warn!("Could not prune cached module {:?}: {:?}", path, error);
+            }
+        }
+    }
+}
+
+/// Everything except the module's own bytes that affects its compiled code
+fn environment_key(limits: &WrapLimits) -> String {
+    hash(format!(
+        "{} {:?} {} {}",
+        wasmer::VERSION,
+        Target::default().cpu_features(),
+        limits.fuel,
+        limits.max_memory_pages,
+    ))
+}
+
+impl ModuleCache {
+    /// Cache modules inside of `dir`, or never cache them without one
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Self { dir }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(None)
+    }
+
+    fn module_path(&self, bytes: &[u8], limits: &WrapLimits) -> Option<PathBuf> {
+        let path = self
+            .dir
+            .as_ref()?
+            .join(environment_key(limits))
+            .join(hash(bytes))
+            .with_extension(MODULE_EXTENSION);
+        Some(path)
+    }
+
+    /// Load the compiled module from the cache, or compile it and add it to the cache
+    pub fn load_or_compile(
+        &self,
+        store: &Store,
+        bytes: &[u8],
+        limits: &WrapLimits,
+    ) -> Result<Module, CompileError> {
+        let Some(path) = self.module_path(bytes, limits) else {
+            return Module::new(store, bytes);
+        };
+
+        if path.exists() {
+            match read_verified(&path) {
+                // Safety: the module is intact and was serialized into a directory keyed by the
+                // wasmer version and CPU features it was compiled for. Like every file in the user's
+                // cache directory, it is trusted as much as the user's account
+                Ok(Some(serialized)) => match unsafe { Module::deserialize(store, serialized) } {
+                    Ok(module) => {
+                        if let Err(error) = touch(&path) {
+                            warn!("Could not touch cached module {:?}: {:?}", path, error);
+                        }
+                        return Ok(module);
+                    }
+                    Err(error) => warn!("Could not read cached module {:?}: {:?}", path, error),
+                },
+                Ok(None) => warn!("Cached module {:?} doesn't match its checksum", path),
+                Err(error) => warn!("Could not read cached module {:?}: {:?}", path, error),
+            }
+        }
+
+        let module = Module::new(store, bytes)?;
+        if let Err(error) = Self::save(&module, &path) {
+            warn!("Could not cache module {:?}: {:?}", path, error);
+        }
+        Ok(module)
+    }
+
+    fn save(module: &Module, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = path.parent().ok_or("module path has no parent")?;
+        fs::create_dir_all(dir)?;
+
+        // Another instance could be reading the module, so never leave it half written
+        let temporary = path.with_extension("tmp");
+        write_verified(&temporary, &module.serialize()?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Delete modules compiled for another wasmer version, CPU or limits than any of `limits`, then
+    /// the modules that haven't been loaded recently or don't fit in the cache
+    pub fn prune(&self, limits: &[&WrapLimits]) {
+        let Some(dir) = self.dir.as_ref() else {
+            return;
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        let current: Vec<String> = limits
+            .iter()
+            .map(|limits| environment_key(limits))
+            .collect();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let stale = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| !current.iter().any(|key| key == name));
+            if stale {
+                if let Err(error) = fs::remove_dir_all(entry.path()) {
+                    warn!(
+                        "Could not prune module cache {:?}: {:?}",
+                        entry.path(),
+                        error
+                    );
+                }
+            }
+        }
+
+        let dirs: Vec<PathBuf> = current.iter().map(|key| dir.join(key)).collect();
+        evict(&dirs, MAX_MODULE_AGE, MAX_CACHE_SIZE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_dir;
+    use super::*;
+
+    fn write_module(dir: &Path, name: &str, len: usize, age: Duration) -> PathBuf {
+        let path = dir.join(name).with_extension(MODULE_EXTENSION);
+        fs::write(&path, vec![0; len]).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_checksum() {
+        let dir = test_dir("modules-checksum");
+        let path = dir.join("module.bin");
+        write_verified(&path, b"serialized").unwrap();
+        assert_eq!(read_verified(&path).unwrap(), Some(b"serialized".to_vec()));
+
+        let mut bytes = fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(read_verified(&path).unwrap(), None);
+
+        fs::write(&path, b"short").unwrap();
+        assert_eq!(read_verified(&path).unwrap(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_evict() {
+        let dir = test_dir("modules-evict");
+        let hour = Duration::from_secs(60 * 60);
+        let recent = write_module(&dir, "recent", 4, Duration::ZERO);
+        let older = write_module(&dir, "older", 4, hour);
+        let oldest = write_module(&dir, "oldest", 4, 2 * hour);
+        let expired = write_module(&dir, "expired", 1, 48 * hour);
+        let other = dir.join("other.tmp");
+        fs::write(&other, vec![0; 100]).unwrap();
+
+        evict(&[dir.clone()], 24 * hour, 8);
+        assert!(recent.exists());
+        assert!(older.exists());
+        assert!(!oldest.exists());
+        assert!(!expired.exists());
+        assert!(other.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+use super::host::HostEnv;
+use super::loader::{SignalError, Wraps};
+use super::trap::{TrapKind, WrapTrap};
+use bevy::prelude::*;
+use hmny_common::native::NativeWrap;
+use std::panic::{self, AssertUnwindSafe};
+use url::Url;
+
+/// A wrap linked into the host, called directly instead of through wasm
+pub(super) struct NativeRuntime {
+    wrap: NativeWrap,
+    /// Only kept so native wraps are configured like any other. They call the std fallbacks of host
+    /// functions rather than the host itself
+    pub env: HostEnv,
+}
+
+impl NativeRuntime {
+    pub fn new(wrap: NativeWrap) -> Self {
+        Self {
+            wrap,
+            env: HostEnv::new(),
+        }
+    }
+
+    pub fn send_raw(
+        &mut self,
+        query_id: u64,
+        input_signal_bytes: &[u8],
+    ) -> Result<Vec<u8>, SignalError> {
+        // A panicking wrap must not take the host down with it, so it traps like it would in wasm
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            (self.wrap.signal)(query_id, input_signal_bytes)
+        }))
+        .map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".into());
+
+            SignalError::Trapped(WrapTrap {
+                kind: TrapKind::Panic,
+                message: format!("panicked: {}", message),
+                panic: None,
+                backtrace: Vec::new(),
+            })
+        })
+    }
+}
+
+/// Where a native wrap is said to be loaded from
+pub(super) fn native_source(wrap: &NativeWrap) -> Url {
+    Url::parse(&format!("native:{}", wrap.name)).expect("Crate names are valid in urls")
+}
+
+/// Wraps linked into the host, loaded on startup
+///
+/// Defaults to the wraps of this repository. Insert this resource before adding the
+/// [`WrapPlugin`](super::WrapPlugin) to debug other wraps
+#[derive(Resource, Clone, Debug)]
+pub struct NativeWraps(pub Vec<NativeWrap>);
+
+impl Default for NativeWraps {
+    fn default() -> Self {
+        Self(vec![
+            homescreen_default::native_wrap(),
+            mimetype_markdown::native_wrap(),
+            test_wrap::native_wrap(),
+        ])
+    }
+}
+
+fn load_native_wraps_system(mut wraps: ResMut<Wraps>, native_wraps: Res<NativeWraps>) {
+    for native in native_wraps.0.iter() {
+        match wraps.load_native(*native) {
+            Ok(()) => warn!("Loaded {:?} natively, without any limits", native.name),
+            Err(error) => error!("Could not load {:?} natively: {:?}", native.name, error),
+        }
+    }
+}
+
+pub struct WrapNativePlugin;
+
+impl Plugin for WrapNativePlugin {
+    fn build(&self, app: &mut App) {
+        // Native wraps have the highest precedence, so they replace wasm builds of the same wraps
+        app.init_resource::<NativeWraps>()
+            .add_systems(PreStartup, load_native_wraps_system);
+    }
+}
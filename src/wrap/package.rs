@@ -0,0 +1,293 @@
+use super::user_data_dir;
+use bevy::{prelude::*, utils::HashMap};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hmny_common::prelude::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Magic bytes prefixing every wrap package, telling it apart from a bare wasm module
+pub const PACKAGE_MAGIC: &[u8; 8] = b"HMNYWRAP";
+const WASM_MAGIC: &[u8; 4] = b"\0asm";
+const TRUST_STORE_FILE: &str = "trusted_publishers.txt";
+const ALLOW_UNSIGNED_FLAG: &str = "--allow-unsigned-wraps";
+
+#[derive(Debug)]
+pub enum PackageError {
+    InvalidFormat(String),
+    InvalidKey(String),
+    InvalidSignature(String),
+    /// None of the keys in the signature chain are trusted
+    UntrustedPublisher(String),
+    /// A bare wasm module was loaded without `--allow-unsigned-wraps`
+    Unsigned,
+}
+
+#[derive(Clone, Debug, Decode, Encode)]
+pub struct PackageSignature {
+    pub publisher: String,
+    pub public_key: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+/// A wasm module together with a detached chain of signatures.
+///
+/// The first signature signs the wasm module itself, and every following signature endorses the
+/// publisher and key of the one before it, up to a publisher in the user's [`TrustStore`].
+#[derive(Clone, Debug, Decode, Encode)]
+pub struct WrapPackage {
+    pub wasm: Vec<u8>,
+    pub signatures: Vec<PackageSignature>,
+}
+
+fn endorsement_message(signature: &PackageSignature) -> Vec<u8> {
+    let mut message = b"hmny-endorse:".to_vec();
+    message.extend_from_slice(&signature.public_key);
+    message.extend_from_slice(signature.publisher.as_bytes());
+    message
+}
+
+impl WrapPackage {
+    /// Create a package signed by its publisher
+    pub fn sign(wasm: Vec<u8>, publisher: &str, key: &SigningKey) -> Self {
+        let signature = PackageSignature {
+            publisher: publisher.into(),
+            public_key: key.verifying_key().to_bytes(),
+            signature: key.sign(&wasm).to_bytes(),
+        };
+
+        Self {
+            wasm,
+            signatures: vec![signature],
+        }
+    }
+
+    /// Extend the chain with a signature vouching for the last signer
+    pub fn endorse(&mut self, publisher: &str, key: &SigningKey) {
+        let last = self.signatures.last().expect("package has no signatures");
+        let signature = key.sign(&endorsement_message(last)).to_bytes();
+        self.signatures.push(PackageSignature {
+            publisher: publisher.into(),
+            public_key: key.verifying_key().to_bytes(),
+            signature,
+        });
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PackageError> {
+        let bytes = bytes
+            .strip_prefix(PACKAGE_MAGIC)
+            .ok_or_else(|| PackageError::InvalidFormat("missing package header".into()))?;
+        let (package, _) = bincode::decode_from_slice(bytes, bincode::config::standard())
+            .map_err(|error| PackageError::InvalidFormat(format!("{}", error)))?;
+        Ok(package)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PACKAGE_MAGIC.to_vec();
+        bincode::encode_into_std_write(self, &mut bytes, bincode::config::standard())
+            .expect("Could not encode package");
+        bytes
+    }
+
+    /// Check the signature chain, returning the verified publisher of the wrap
+    pub fn verify(&self, trust_store: &TrustStore) -> Result<Publisher, PackageError> {
+        let mut message = self.wasm.clone();
+        let mut chain = Vec::new();
+
+        for signature in self.signatures.iter() {
+            let key = VerifyingKey::from_bytes(&signature.public_key)
+                .map_err(|error| PackageError::InvalidKey(format!("{}", error)))?;
+            key.verify(&message, &Signature::from_bytes(&signature.signature))
+                .map_err(|_| PackageError::InvalidSignature(signature.publisher.clone()))?;
+
+            // Stop at the first trusted key, using the name the user trusts it under
+            if let Some(name) = trust_store.get(&signature.public_key) {
+                let mut publisher = Publisher::new(name, vec![]);
+                for name in chain.iter().rev() {
+                    publisher = Publisher::new(name, vec![publisher]);
+                }
+                return Ok(publisher);
+            }
+
+            chain.push(signature.publisher.clone());
+            message = endorsement_message(signature);
+        }
+
+        Err(PackageError::UntrustedPublisher(
+            chain.last().cloned().unwrap_or_default(),
+        ))
+    }
+}
+
+/// Public keys of publishers the user trusts to sign wraps
+///
+/// Read from the user data directory. Unsigned wraps are only allowed with
+/// `--allow-unsigned-wraps`
+pub struct TrustStore {
+    keys: HashMap<[u8; 32], String>,
+    /// Whether bare, unsigned wasm modules may be loaded. Used while developing wraps
+    pub allow_unsigned: bool,
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        let allow_unsigned = env::args().skip(1).any(|arg| arg == ALLOW_UNSIGNED_FLAG);
+        if allow_unsigned {
+            warn!("Loading unsigned wraps, their publishers can't be verified");
+        }
+
+        let mut trust_store = Self {
+            keys: HashMap::new(),
+            allow_unsigned,
+        };
+        if let Some(path) = user_data_dir().map(|dir| dir.join(TRUST_STORE_FILE)) {
+            if let Err(error) = trust_store.load_from_file(&path) {
+                warn!("Could not read trust store {:?}: {:?}", path, error);
+            }
+        }
+        trust_store
+    }
+}
+
+impl TrustStore {
+    pub fn trust(&mut self, name: &str, public_key: [u8; 32]) {
+        self.keys.insert(public_key, name.into());
+    }
+
+    pub fn get(&self, public_key: &[u8; 32]) -> Option<&String> {
+        self.keys.get(public_key)
+    }
+
+    /// Read trusted keys from a file with one `<hex public key> <publisher name>` per line
+    pub fn load_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PackageError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(path)
+            .map_err(|error| PackageError::InvalidFormat(format!("{}", error)))?;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, name) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| PackageError::InvalidKey(line.into()))?;
+            let public_key = hex::decode(key)
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| PackageError::InvalidKey(key.into()))?;
+            self.trust(name.trim(), public_key);
+        }
+
+        Ok(())
+    }
+
+    /// Extract the wasm module from a package or bare module, along with its verified publisher
+    pub fn open(&self, bytes: &[u8]) -> Result<(Vec<u8>, Option<Publisher>), PackageError> {
+        if bytes.starts_with(WASM_MAGIC) {
+            return if self.allow_unsigned {
+                Ok((bytes.to_vec(), None))
+            } else {
+                Err(PackageError::Unsigned)
+            };
+        }
+
+        let package = WrapPackage::from_bytes(bytes)?;
+        let publisher = package.verify(self)?;
+        Ok((package.wasm, Some(publisher)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn trusting(name: &str, key: &SigningKey) -> TrustStore {
+        let mut trust_store = TrustStore {
+            keys: HashMap::new(),
+            allow_unsigned: false,
+        };
+        trust_store.trust(name, key.verifying_key().to_bytes());
+        trust_store
+    }
+
+    #[test]
+    fn test_trusted_signer() {
+        let alice = key(1);
+        let package = WrapPackage::sign(WASM.to_vec(), "alice", &alice);
+
+        // The publisher is named the way the user trusts it, not the way the package claims
+        let trust_store = trusting("Alice", &alice);
+        let (wasm, publisher) = trust_store.open(&package.to_bytes()).unwrap();
+        assert_eq!(wasm, WASM);
+        assert_eq!(publisher, Some(Publisher::new("Alice", vec![])));
+
+        assert!(matches!(
+            trust_store.open(WASM),
+            Err(PackageError::Unsigned)
+        ));
+    }
+
+    #[test]
+    fn test_endorsed_chain() {
+        let (alice, bob) = (key(1), key(2));
+        let mut package = WrapPackage::sign(WASM.to_vec(), "alice", &alice);
+        package.endorse("bob", &bob);
+
+        let publisher = package.verify(&trusting("Bob", &bob)).unwrap();
+        assert_eq!(
+            publisher,
+            Publisher::new("alice", vec![Publisher::new("Bob", vec![])])
+        );
+    }
+
+    #[test]
+    fn test_tampered_wasm() {
+        let alice = key(1);
+        let mut package = WrapPackage::sign(WASM.to_vec(), "alice", &alice);
+        package.wasm.push(0);
+
+        let result = package.verify(&trusting("Alice", &alice));
+        assert!(matches!(result, Err(PackageError::InvalidSignature(name)) if name == "alice"));
+    }
+
+    #[test]
+    fn test_broken_endorsement() {
+        let (alice, bob) = (key(1), key(2));
+        let mut package = WrapPackage::sign(WASM.to_vec(), "alice", &alice);
+        package.endorse("bob", &bob);
+
+        // Bob vouched for alice's name and key, so neither can be swapped afterwards
+        package.signatures[0].publisher = "mallory".into();
+        let result = package.verify(&trusting("Bob", &bob));
+        assert!(matches!(result, Err(PackageError::InvalidSignature(name)) if name == "bob"));
+
+        let mut package = WrapPackage::sign(WASM.to_vec(), "mallory", &key(3));
+        package.signatures.push(
+            WrapPackage::sign(WASM.to_vec(), "bob", &bob)
+                .signatures
+                .remove(0),
+        );
+        let result = package.verify(&trusting("Bob", &bob));
+        assert!(matches!(result, Err(PackageError::InvalidSignature(name)) if name == "bob"));
+    }
+
+    #[test]
+    fn test_untrusted_root() {
+        let (alice, bob) = (key(1), key(2));
+        let mut package = WrapPackage::sign(WASM.to_vec(), "alice", &alice);
+        package.endorse("bob", &bob);
+
+        let result = package.verify(&trusting("Carol", &key(3)));
+        assert!(matches!(result, Err(PackageError::UntrustedPublisher(name)) if name == "bob"));
+    }
+}
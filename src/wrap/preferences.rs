@@ -0,0 +1,123 @@
+use super::WrapId;
+use bevy::utils::HashMap;
+use hmny_common::prelude::*;
+use std::cmp::Reverse;
+
+/// Decides which wrap handles a signal when several wraps were loaded for the same key
+#[derive(Clone, Default, Debug)]
+pub struct WrapPreferences {
+    /// Wrap the user picked for a key. Always tried first
+    defaults: HashMap<WrapKey, WrapId>,
+    /// Wraps with a higher priority are tried first. Unlisted wraps have a priority of 0
+    priorities: HashMap<WrapId, i32>,
+}
+
+impl WrapPreferences {
+    pub fn set_default(&mut self, key: WrapKey, id: WrapId) {
+        self.defaults.insert(key, id);
+    }
+
+    pub fn clear_default(&mut self, key: &WrapKey) {
+        self.defaults.remove(key);
+    }
+
+    /// Default picked for a key, or else for the most specific key accepting it, so a default for
+    /// `text/markdown` also applies to `text/markdown; charset=utf-8`
+    pub fn get_default(&self, key: &WrapKey) -> Option<&WrapId> {
+        self.defaults.get(key).or_else(|| {
+            self.defaults
+                .iter()
+                .filter(|(default_key, _)| default_key.accepts(key))
+                .max_by_key(|(default_key, _)| default_key.specificity())
+                .map(|(_, id)| id)
+        })
+    }
+
+    pub fn set_priority(&mut self, id: WrapId, priority: i32) {
+        self.priorities.insert(id, priority);
+    }
+
+    pub fn get_priority(&self, id: &WrapId) -> i32 {
+        self.priorities.get(id).copied().unwrap_or(0)
+    }
+
+    /// Sorting by this key puts the most preferred wrap first
+    pub fn rank(&self, key: &WrapKey, id: &WrapId) -> (bool, Reverse<i32>) {
+        let is_default = self.get_default(key) == Some(id);
+        (!is_default, Reverse(self.get_priority(id)))
+    }
+
+    /// Keep the wraps able to handle a key, given as `(id, key, load order, item)`, from most to
+    /// least preferred
+    pub(super) fn rank_candidates<'a, T>(
+        &self,
+        key: &WrapKey,
+        wraps: impl Iterator<Item = (&'a WrapId, &'a WrapKey, u64, T)>,
+    ) -> Vec<T> {
+        let mut candidates: Vec<_> = wraps
+            .filter(|(_, wrap_key, _, _)| wrap_key.accepts(key))
+            .collect();
+        // Wraps for a specific mimetype go before wildcard ones, unless the user prefers otherwise
+        candidates.sort_by_key(|(id, wrap_key, load_order, _)| {
+            (
+                self.rank(key, id),
+                Reverse(wrap_key.specificity()),
+                *load_order,
+            )
+        });
+        candidates.into_iter().map(|(_, _, _, item)| item).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(publisher: &str, name: &str) -> WrapId {
+        WrapId {
+            publisher: publisher.into(),
+            name: name.into(),
+        }
+    }
+
+    fn mimetype(mime_type: &str) -> WrapKey {
+        WrapKey::Mimetype(mime_type.parse().unwrap())
+    }
+
+    #[test]
+    fn test_default_accepts() {
+        let mut preferences = WrapPreferences::default();
+        preferences.set_default(mimetype("text/*"), id("Harmony", "text"));
+        preferences.set_default(mimetype("text/markdown"), id("Harmony", "markdown"));
+
+        let key = mimetype("text/markdown; charset=utf-8");
+        assert_eq!(
+            preferences.get_default(&key),
+            Some(&id("Harmony", "markdown"))
+        );
+        let key = mimetype("text/plain");
+        assert_eq!(preferences.get_default(&key), Some(&id("Harmony", "text")));
+        assert_eq!(preferences.get_default(&mimetype("image/png")), None);
+    }
+
+    #[test]
+    fn test_rank_by_id() {
+        let mut preferences = WrapPreferences::default();
+        let key = mimetype("text/markdown");
+        let ours = id("Harmony", "markdown");
+        let theirs = id("Someone else", "markdown");
+        preferences.set_priority(theirs.clone(), 1);
+
+        let candidates = [(&ours, &key, 0, "ours"), (&theirs, &key, 1, "theirs")];
+        assert_eq!(
+            preferences.rank_candidates(&key, candidates.into_iter()),
+            vec!["theirs", "ours"]
+        );
+
+        preferences.set_default(key.clone(), ours.clone());
+        assert_eq!(
+            preferences.rank_candidates(&key, candidates.into_iter()),
+            vec!["ours", "theirs"]
+        );
+    }
+}
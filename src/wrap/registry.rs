@@ -0,0 +1,557 @@
+use super::search_paths::INSTALLED_WRAPS_DIR;
+use super::{is_wrap_file, user_data_dir, WrapLoadFailed, WrapOrigin, WrapSearchPaths, Wraps};
+use bevy::prelude::*;
+use semver::{Comparator, Op, Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const REGISTRY_INDEX_DIR: &str = "index";
+const MANIFEST_FILE: &str = "manifest.json";
+
+pub struct WrapRegistryPlugin;
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    InvalidManifest(serde_json::Error),
+    /// Names must not contain path separators or `..`, since they are used as directory names
+    InvalidName(String),
+    WrapNotFound(String),
+    NoMatchingVersion(String, VersionReq),
+    NotInstalled(String),
+    NothingToRollBack(String),
+    /// The wrap was installed but could not be loaded, which is reported by a [`WrapLoadFailed`]
+    LoadFailed(PathBuf),
+}
+
+impl From<std::io::Error> for RegistryError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct InstalledVersion {
+    version: Version,
+    /// Either a signed package or a bare module
+    extension: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct InstalledWrap {
+    active: InstalledVersion,
+    /// Previously active versions kept around for rollbacks, most recent last
+    history: Vec<InstalledVersion>,
+}
+
+impl InstalledWrap {
+    /// Make a version the active one, keeping the previously active one for rollbacks
+    fn activate(&mut self, active: InstalledVersion) {
+        if self.active != active {
+            // A version is only ever kept once, so rollbacks can safely delete it
+            self.history.retain(|version| *version != active);
+            let previous = std::mem::replace(&mut self.active, active);
+            self.history.push(previous);
+        }
+    }
+
+    /// Go back to the previously active version, returning the one it replaced
+    fn roll_back(&mut self) -> Option<InstalledVersion> {
+        let restored = self.history.pop()?;
+        Some(std::mem::replace(&mut self.active, restored))
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+struct Manifest {
+    wraps: BTreeMap<String, InstalledWrap>,
+}
+
+fn check_name(name: &str) -> Result<(), RegistryError> {
+    let invalid = name.is_empty()
+        || name == "."
+        || name.contains("..")
+        || name.contains(['/', '\\', ':', '\0']);
+    if invalid {
+        return Err(RegistryError::InvalidName(name.into()));
+    }
+    Ok(())
+}
+
+/// Newest of the versions, sorted from oldest to newest, matching the requirement
+fn resolve(
+    versions: Vec<(Version, PathBuf)>,
+    requirement: &VersionReq,
+) -> Option<(Version, PathBuf)> {
+    versions
+        .into_iter()
+        .rev()
+        .find(|(version, _)| requirement.matches(version))
+}
+
+/// Newest of the versions semver compatible with the current one, if it is any newer
+fn resolve_update(
+    versions: Vec<(Version, PathBuf)>,
+    current: &Version,
+) -> Option<(Version, PathBuf)> {
+    // Built by hand, since requirements can't be parsed from versions with build metadata
+    let requirement = VersionReq {
+        comparators: vec![Comparator {
+            op: Op::Caret,
+            major: current.major,
+            minor: Some(current.minor),
+            patch: Some(current.patch),
+            pre: current.pre.clone(),
+        }],
+    };
+    resolve(versions, &requirement).filter(|(version, _)| version > current)
+}
+
+/// Installs wraps from a local index into the install directory, and keeps [`Wraps`] in sync.
+///
+/// The index is a directory holding one directory per wrap, each containing its versions as
+/// `<version>.wrap` packages (or `<version>.wasm` modules while developing).
+#[derive(Resource)]
+pub struct WrapRegistry {
+    index_dir: PathBuf,
+    install_dir: PathBuf,
+    manifest: Manifest,
+    /// Wraps that failed to load after being installed, updated or rolled back
+    failures: Vec<WrapLoadFailed>,
+}
+
+impl FromWorld for WrapRegistry {
+    fn from_world(world: &mut World) -> Self {
+        // Without a user data directory, wraps can still be installed until the next reboot
+        let fallback_dir = env::temp_dir().join("hmny");
+        let index_dir = user_data_dir()
+            .unwrap_or_else(|| fallback_dir.clone())
+            .join(REGISTRY_INDEX_DIR);
+        // The registry loads what it installed by itself, so it keeps the default install directory
+        // when the search paths leave it out
+        let install_dir = world
+            .get_resource_or_insert_with(WrapSearchPaths::default)
+            .installed_dir()
+            .map(Path::to_path_buf)
+            .or_else(|| {
+                WrapSearchPaths::defaults()
+                    .installed_dir()
+                    .map(Path::to_path_buf)
+            })
+            .unwrap_or_else(|| fallback_dir.join(INSTALLED_WRAPS_DIR));
+
+        Self::new(&index_dir, &install_dir).unwrap_or_else(|error| {
+            error!("Could not read wrap registry manifest: {:?}", error);
+            Self {
+                index_dir,
+                install_dir,
+                manifest: Manifest::default(),
+                failures: Vec::new(),
+            }
+        })
+    }
+}
+
+impl WrapRegistry {
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(
+        index_dir: P,
+        install_dir: Q,
+    ) -> Result<Self, RegistryError> {
+        let install_dir = install_dir.as_ref().to_path_buf();
+        let manifest_path = install_dir.join(MANIFEST_FILE);
+        let manifest = if manifest_path.exists() {
+            serde_json::from_slice(&fs::read(manifest_path)?)
+                .map_err(RegistryError::InvalidManifest)?
+        } else {
+            Manifest::default()
+        };
+
+        Ok(Self {
+            index_dir: index_dir.as_ref().to_path_buf(),
+            install_dir,
+            manifest,
+            failures: Vec::new(),
+        })
+    }
+
+    fn save_manifest(&self) -> Result<(), RegistryError> {
+        fs::create_dir_all(&self.install_dir)?;
+        let manifest =
+            serde_json::to_vec_pretty(&self.manifest).map_err(RegistryError::InvalidManifest)?;
+        fs::write(self.install_dir.join(MANIFEST_FILE), manifest)?;
+        Ok(())
+    }
+
+    /// Names of every wrap available in the index
+    pub fn available(&self) -> Result<Vec<String>, RegistryError> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(&self.index_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Every version of a wrap available in the index, sorted from oldest to newest
+    pub fn available_versions(&self, name: &str) -> Result<Vec<(Version, PathBuf)>, RegistryError> {
+        check_name(name)?;
+        let wrap_dir = self.index_dir.join(name);
+        if !wrap_dir.is_dir() {
+            return Err(RegistryError::WrapNotFound(name.into()));
+        }
+
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(wrap_dir)? {
+            let path = entry?.path();
+            let version = path
+                .file_stem()
+                .and_then(|stem| Version::parse(&stem.to_string_lossy()).ok());
+            match version {
+                Some(version) if is_wrap_file(&path) => versions.push((version, path)),
+                _ => warn!("Ignoring unknown file in wrap registry index {:?}", path),
+            }
+        }
+        versions.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(versions)
+    }
+
+    pub fn installed(&self) -> impl Iterator<Item = (&String, &Version)> {
+        self.manifest
+            .wraps
+            .iter()
+            .map(|(name, installed)| (name, &installed.active.version))
+    }
+
+    fn installed_path(&self, name: &str, installed: &InstalledVersion) -> PathBuf {
+        self.install_dir
+            .join(name)
+            .join(format!("{}.{}", installed.version, installed.extension))
+    }
+
+    fn active_path(&self, name: &str) -> Result<PathBuf, RegistryError> {
+        check_name(name)?;
+        let installed = self
+            .manifest
+            .wraps
+            .get(name)
+            .ok_or_else(|| RegistryError::NotInstalled(name.into()))?;
+        Ok(self.installed_path(name, &installed.active))
+    }
+
+    /// Swap the currently loaded version of a wrap for the active one
+    fn reload(
+        &mut self,
+        wraps: &mut Wraps,
+        previous: Option<PathBuf>,
+        name: &str,
+    ) -> Result<(), RegistryError> {
+        if let Some(previous) = previous {
+            if let Err(error) = wraps.unload_from_path(&previous) {
+                warn!("Could not unload {:?}: {:?}", previous, error);
+            }
+        }
+
+        let path = self.active_path(name)?;
+        if let Err(error) = wraps.load_from_path(&path, WrapOrigin::Installed) {
+            error!("Error while attempting to load installed wrap {}", name);
+            error!("    {:?}", error);
+            self.failures.push(WrapLoadFailed {
+                path: path.clone(),
+                error,
+            });
+            return Err(RegistryError::LoadFailed(path));
+        }
+        Ok(())
+    }
+
+    /// Install the newest version of a wrap matching the requirement
+    pub fn install(
+        &mut self,
+        wraps: &mut Wraps,
+        name: &str,
+        requirement: &VersionReq,
+    ) -> Result<Version, RegistryError> {
+        let (version, source) = resolve(self.available_versions(name)?, requirement)
+            .ok_or_else(|| RegistryError::NoMatchingVersion(name.into(), requirement.clone()))?;
+        self.install_version(wraps, name, version, &source)
+    }
+
+    fn install_version(
+        &mut self,
+        wraps: &mut Wraps,
+        name: &str,
+        version: Version,
+        source: &Path,
+    ) -> Result<Version, RegistryError> {
+        let active = InstalledVersion {
+            version: version.clone(),
+            extension: source
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        };
+        let destination = self.installed_path(name, &active);
+        fs::create_dir_all(destination.parent().unwrap())?;
+        fs::copy(source, &destination)?;
+
+        let previous = self.active_path(name).ok();
+        match self.manifest.wraps.get_mut(name) {
+            Some(installed) => installed.activate(active),
+            None => {
+                let installed = InstalledWrap {
+                    active,
+                    history: vec![],
+                };
+                self.manifest.wraps.insert(name.into(), installed);
+            }
+        }
+        self.save_manifest()?;
+
+        info!("Installed wrap {} {}", name, version);
+        self.reload(wraps, previous, name)?;
+        Ok(version)
+    }
+
+    /// Update a wrap to the newest semver compatible version, returning it if there was one
+    pub fn update(
+        &mut self,
+        wraps: &mut Wraps,
+        name: &str,
+    ) -> Result<Option<Version>, RegistryError> {
+        let current = self
+            .manifest
+            .wraps
+            .get(name)
+            .map(|installed| installed.active.version.clone())
+            .ok_or_else(|| RegistryError::NotInstalled(name.into()))?;
+
+        match resolve_update(self.available_versions(name)?, &current) {
+            Some((newest, source)) => self.install_version(wraps, name, newest, &source).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn update_all(
+        &mut self,
+        wraps: &mut Wraps,
+    ) -> Vec<(String, Result<Option<Version>, RegistryError>)> {
+        let names: Vec<String> = self.manifest.wraps.keys().cloned().collect();
+        names
+            .into_iter()
+            .map(|name| {
+                let result = self.update(wraps, &name);
+                (name, result)
+            })
+            .collect()
+    }
+
+    pub fn uninstall(&mut self, wraps: &mut Wraps, name: &str) -> Result<(), RegistryError> {
+        // Also makes sure the name is valid, before removing its directory
+        let path = self.active_path(name)?;
+        if let Err(error) = wraps.unload_from_path(&path) {
+            warn!("Could not unload {:?}: {:?}", path, error);
+        }
+
+        self.manifest.wraps.remove(name);
+        self.save_manifest()?;
+        fs::remove_dir_all(self.install_dir.join(name))?;
+
+        info!("Uninstalled wrap {}", name);
+        Ok(())
+    }
+
+    /// Go back to the previously active version of a wrap
+    pub fn rollback(&mut self, wraps: &mut Wraps, name: &str) -> Result<Version, RegistryError> {
+        let previous = self.active_path(name)?;
+        let installed = self
+            .manifest
+            .wraps
+            .get_mut(name)
+            .ok_or_else(|| RegistryError::NotInstalled(name.into()))?;
+        // The rolled back version is dropped, since it has been superseded
+        let discarded = installed
+            .roll_back()
+            .ok_or_else(|| RegistryError::NothingToRollBack(name.into()))?;
+        let version = installed.active.version.clone();
+        self.save_manifest()?;
+        if let Err(error) = wraps.unload_from_path(&previous) {
+            warn!("Could not unload {:?}: {:?}", previous, error);
+        }
+        fs::remove_file(self.installed_path(name, &discarded))?;
+
+        info!("Rolled back wrap {} to {}", name, version);
+        self.reload(wraps, None, name)?;
+        Ok(version)
+    }
+
+    /// Load the active version of every installed wrap, returning the ones that failed
+    pub fn load_installed(&self, wraps: &mut Wraps) -> Vec<WrapLoadFailed> {
+        let mut failures = Vec::new();
+        for (name, installed) in self.manifest.wraps.iter() {
+            if let Err(error) = check_name(name) {
+                error!("Skipping installed wrap: {:?}", error);
+                continue;
+            }
+            let path = self.installed_path(name, &installed.active);
+            if let Err(error) = wraps.load_from_path(&path, WrapOrigin::Installed) {
+                error!("Error while attempting to load installed wrap {}", name);
+                error!("    {:?}", error);
+                failures.push(WrapLoadFailed { path, error });
+            }
+        }
+        failures
+    }
+}
+
+fn load_installed_wraps_system(
+    registry: Res<WrapRegistry>,
+    mut wraps: ResMut<Wraps>,
+    mut failures: EventWriter<WrapLoadFailed>,
+) {
+    failures.send_batch(registry.load_installed(&mut wraps));
+}
+
+fn send_registry_failures_system(
+    mut registry: ResMut<WrapRegistry>,
+    mut failures: EventWriter<WrapLoadFailed>,
+) {
+    failures.send_batch(registry.failures.drain(..));
+}
+
+impl Plugin for WrapRegistryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WrapRegistry>()
+            .add_systems(PreStartup, load_installed_wraps_system)
+            .add_systems(
+                Last,
+                send_registry_failures_system
+                    .run_if(|registry: Res<WrapRegistry>| !registry.failures.is_empty()),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_dir;
+    use super::*;
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    fn installed(version: &str) -> InstalledVersion {
+        InstalledVersion {
+            version: self::version(version),
+            extension: "wrap".into(),
+        }
+    }
+
+    /// An index holding a single wrap with the given files
+    fn test_index(name: &str, files: &[&str]) -> WrapRegistry {
+        let dir = test_dir(&format!("registry-{}", name));
+        let wrap_dir = dir.join("index").join("my_wrap");
+        fs::create_dir_all(&wrap_dir).unwrap();
+        for file in files {
+            fs::write(wrap_dir.join(file), []).unwrap();
+        }
+        WrapRegistry::new(dir.join("index"), dir.join("installed")).unwrap()
+    }
+
+    fn versions(registry: &WrapRegistry) -> Vec<(Version, PathBuf)> {
+        registry.available_versions("my_wrap").unwrap()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let registry = test_index(
+            "resolve",
+            &[
+                "1.0.0.wrap",
+                "1.2.0.wasm",
+                "2.0.0.wrap",
+                "notes.txt",
+                "next.wrap",
+            ],
+        );
+        let found: Vec<Version> = versions(&registry)
+            .into_iter()
+            .map(|(version, _)| version)
+            .collect();
+        assert_eq!(
+            found,
+            [version("1.0.0"), version("1.2.0"), version("2.0.0")]
+        );
+
+        let resolved = |requirement: &str| {
+            resolve(
+                versions(&registry),
+                &VersionReq::parse(requirement).unwrap(),
+            )
+            .map(|(version, _)| version)
+        };
+        assert_eq!(resolved("*"), Some(version("2.0.0")));
+        assert_eq!(resolved("^1"), Some(version("1.2.0")));
+        assert_eq!(resolved("=1.0.0"), Some(version("1.0.0")));
+        assert_eq!(resolved(">2"), None);
+        assert!(matches!(
+            registry.available_versions("other"),
+            Err(RegistryError::WrapNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_update() {
+        let registry = test_index(
+            "update",
+            &["1.0.0+build.1.wrap", "1.1.0+build.2.wrap", "2.0.0.wrap"],
+        );
+        let update = |current: &str| {
+            resolve_update(versions(&registry), &version(current)).map(|(version, _)| version)
+        };
+
+        // Build metadata doesn't get in the way of finding compatible versions
+        assert_eq!(update("1.0.0+build.1"), Some(version("1.1.0+build.2")));
+        assert_eq!(update("1.1.0+build.2"), None);
+        assert_eq!(update("2.0.0"), None);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut wrap = InstalledWrap {
+            active: installed("1.0.0"),
+            history: vec![],
+        };
+        wrap.activate(installed("1.1.0"));
+        wrap.activate(installed("1.1.0"));
+        wrap.activate(installed("1.2.0"));
+        assert_eq!(wrap.history, [installed("1.0.0"), installed("1.1.0")]);
+
+        // Going back to an older version doesn't keep it twice
+        wrap.activate(installed("1.0.0"));
+        assert_eq!(wrap.history, [installed("1.1.0"), installed("1.2.0")]);
+
+        assert_eq!(wrap.roll_back(), Some(installed("1.0.0")));
+        assert_eq!(wrap.active, installed("1.2.0"));
+        assert_eq!(wrap.roll_back(), Some(installed("1.2.0")));
+        assert_eq!(wrap.roll_back(), None);
+        assert_eq!(wrap.active, installed("1.1.0"));
+    }
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("my_wrap").is_ok());
+        for name in ["", ".", "..", "../wrap", "a/b", "a\\b", "C:wrap"] {
+            assert!(
+                matches!(check_name(name), Err(RegistryError::InvalidName(_))),
+                "{:?} should be invalid",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use super::{LoadedWrap, SignalError, WrapId, WrapPreferences};
+use bevy::prelude::*;
+use hmny_common::prelude::*;
+use std::sync::{Arc, Mutex, RwLock, TryLockError};
+
+struct Route {
+    id: WrapId,
+    key: WrapKey,
+    load_order: u64,
+    wrap: Arc<Mutex<LoadedWrap>>,
+}
+
+#[derive(Default)]
+struct WrapRouterInner {
+    routes: Vec<Route>,
+    preferences: WrapPreferences,
+}
+
+/// Lets wraps signal each other while they run, away from the [`Wraps`](super::Wraps) resource
+///
+/// Holds a copy of the loaded wraps and preferences, kept up to date by [`Wraps`](super::Wraps)
+#[derive(Clone, Default)]
+pub struct WrapRouter {
+    inner: Arc<RwLock<WrapRouterInner>>,
+}
+
+impl WrapRouter {
+    pub(super) fn update<'a>(
+        &self,
+        wraps: impl Iterator<Item = (&'a WrapId, &'a WrapKey, u64, &'a Arc<Mutex<LoadedWrap>>)>,
+        preferences: &WrapPreferences,
+    ) {
+        let routes = wraps
+            .map(|(id, key, load_order, wrap)| Route {
+                id: id.clone(),
+                key: key.clone(),
+                load_order,
+                wrap: wrap.clone(),
+            })
+            .collect();
+
+        let mut inner = self.inner.write().unwrap_or_else(|error| error.into_inner());
+        inner.routes = routes;
+        inner.preferences = preferences.clone();
+    }
+
+    /// Send an encoded signal from one wrap to the wraps of a key, returning the encoded response
+    ///
+    /// Wraps already handling a signal are skipped rather than waited on, since they may be waiting
+    /// on the sender themselves
+    pub fn signal_raw(
+        &self,
+        sender: Option<&WrapId>,
+        key: &WrapKey,
+        query: &SupportedQuery,
+        input_signal_bytes: &[u8],
+    ) -> Result<Vec<u8>, SignalError> {
+        let candidates: Vec<(WrapId, Arc<Mutex<LoadedWrap>>)> = {
+            let inner = self.inner.read().unwrap_or_else(|error| error.into_inner());
+            inner.preferences.rank_candidates(
+                key,
+                inner.routes.iter().map(|route| {
+                    let candidate = (route.id.clone(), route.wrap.clone());
+                    (&route.id, &route.key, route.load_order, candidate)
+                }),
+            )
+        };
+
+        let mut last_error = SignalError::WrapDoesNotExist;
+        for (id, wrap) in candidates {
+            // A wrap is always busy handling the signal it is sending
+            if Some(&id) == sender {
+                continue;
+            }
+
+            let result = match wrap.try_lock() {
+                Ok(mut wrap) => wrap.send_raw_with_policy(query, input_signal_bytes),
+                Err(TryLockError::WouldBlock) => Err(SignalError::WrapBusy),
+                Err(TryLockError::Poisoned(_)) => Err(SignalError::Poisoned),
+            };
+            match result {
+                Err(error) if error.allows_fallback() || matches!(error, SignalError::WrapBusy) => {
+                    debug!(
+                        "{:?} failed to handle a signal from {:?}, trying the next one: {:?}",
+                        id, sender, error
+                    );
+                    last_error = error;
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_error)
+    }
+}
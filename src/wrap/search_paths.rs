@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEV_WRAPS_DIR: &str = "./target/wasm32-unknown-unknown/release";
+/// Where the [`WrapRegistry`](super::WrapRegistry) installs wraps, in the user data directory
+pub(super) const INSTALLED_WRAPS_DIR: &str = "installed";
+const WRAP_PATH_FLAG: &str = "--wrap-path";
+const NO_DEFAULT_WRAP_PATHS_FLAG: &str = "--no-default-wrap-paths";
+
+/// Where a wrap was loaded from
+///
+/// When wraps with the same name are found in several places, the one with the highest precedence
+/// is loaded and the others are shadowed. Later variants take precedence over earlier ones
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WrapOrigin {
+    System,
+    User,
+    /// Installed through the [`WrapRegistry`](super::WrapRegistry), or loaded by hand
+    Installed,
+    Dev,
+    CommandLine,
+    /// Linked into the host with the `native` feature, to debug wraps natively
+    Native,
+}
+
+/// Directories wraps are loaded from and watched in
+///
+/// Defaults to the system and per-user wrap directories, the directory the registry installs wraps
+/// to, plus the cargo target directory in debug builds. Paths given with `--wrap-path <dir>` are
+/// added on top, and `--no-default-wrap-paths` leaves only those. Insert this resource before
+/// adding the [`WrapPlugin`](super::WrapPlugin) to configure it from code instead
+#[derive(Resource, Clone, Debug)]
+pub struct WrapSearchPaths {
+    paths: Vec<(PathBuf, WrapOrigin)>,
+}
+
+impl Default for WrapSearchPaths {
+    fn default() -> Self {
+        Self::from_args(env::args().skip(1))
+    }
+}
+
+/// Per-user data directory, following the XDG base directory spec
+pub fn user_data_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("hmny"));
+    }
+
+    if cfg!(windows) {
+        env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("hmny"))
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share/hmny"))
+    }
+}
+
+/// Per-user cache directory, following the XDG base directory spec
+pub fn user_cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir).join("hmny"));
+    }
+
+    if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("hmny"))
+    } else {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache/hmny"))
+    }
+}
+
+fn system_data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        env::var_os("PROGRAMDATA").map(|dir| PathBuf::from(dir).join("hmny"))
+    } else {
+        Some(PathBuf::from("/usr/share/hmny"))
+    }
+}
+
+impl WrapSearchPaths {
+    pub fn empty() -> Self {
+        Self { paths: Vec::new() }
+    }
+
+    pub fn defaults() -> Self {
+        let mut search_paths = Self::empty();
+        if let Some(dir) = system_data_dir() {
+            search_paths.add(dir.join("wraps"), WrapOrigin::System);
+        }
+        if let Some(dir) = user_data_dir() {
+            search_paths.add(dir.join("wraps"), WrapOrigin::User);
+            search_paths.add(dir.join(INSTALLED_WRAPS_DIR), WrapOrigin::Installed);
+        }
+        if cfg!(debug_assertions) {
+            search_paths.add(DEV_WRAPS_DIR, WrapOrigin::Dev);
+        }
+        search_paths
+    }
+
+    /// Default paths adjusted by the command line flags found in `args`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut use_defaults = true;
+        let mut extra_paths = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == NO_DEFAULT_WRAP_PATHS_FLAG {
+                use_defaults = false;
+            } else if arg == WRAP_PATH_FLAG {
+                match args.next() {
+                    Some(path) => extra_paths.push(path),
+                    None => warn!("Missing directory after {}", WRAP_PATH_FLAG),
+                }
+            } else if let Some(path) = arg
+                .strip_prefix(WRAP_PATH_FLAG)
+                .and_then(|rest| rest.strip_prefix('='))
+            {
+                extra_paths.push(path.into());
+            }
+        }
+
+        let mut search_paths = if use_defaults {
+            Self::defaults()
+        } else {
+            Self::empty()
+        };
+        for path in extra_paths {
+            search_paths.add(path, WrapOrigin::CommandLine);
+        }
+        search_paths
+    }
+
+    pub fn add<P: AsRef<Path>>(&mut self, path: P, origin: WrapOrigin) {
+        self.paths.push((path.as_ref().to_path_buf(), origin));
+    }
+
+    /// Search paths from highest to lowest precedence
+    pub fn iter(&self) -> impl Iterator<Item = (&PathBuf, WrapOrigin)> {
+        let mut paths: Vec<_> = self
+            .paths
+            .iter()
+            .map(|(path, origin)| (path, *origin))
+            .collect();
+        paths.sort_by_key(|(_, origin)| std::cmp::Reverse(*origin));
+        paths.into_iter()
+    }
+
+    /// Directory the registry installs wraps to, if any
+    pub fn installed_dir(&self) -> Option<&Path> {
+        self.iter()
+            .find(|(_, origin)| *origin == WrapOrigin::Installed)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Origin of a wrap file found directly in one of the search paths
+    pub fn origin_of(&self, file: &Path) -> Option<WrapOrigin> {
+        let parent = fs::canonicalize(file.parent()?).ok()?;
+        self.iter()
+            .find(|(path, _)| fs::canonicalize(path).is_ok_and(|path| path == parent))
+            .map(|(_, origin)| origin)
+    }
+}
@@ -0,0 +1,273 @@
+use hmny_common::storage::{check_key, StorageError, StorageResult};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const STORAGE_EXTENSION: &str = "bin";
+
+/// Keep directory and file names portable, and unable to escape the storage directory
+fn escape_file_name(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            escaped.push(byte as char);
+        } else {
+            escaped.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    escaped
+}
+
+struct WrapStorageInner {
+    /// Where entries are saved, or None to keep them in memory
+    path: Option<PathBuf>,
+    /// Read from disk on first use
+    entries: Option<BTreeMap<String, Vec<u8>>>,
+    quota: u64,
+}
+
+impl WrapStorageInner {
+    fn entries(&mut self) -> StorageResult<&mut BTreeMap<String, Vec<u8>>> {
+        if self.entries.is_none() {
+            let entries = match &self.path {
+                Some(path) if path.exists() => {
+                    let bytes = fs::read(path).map_err(unavailable)?;
+                    bincode::decode_from_slice(&bytes, bincode::config::standard())
+                        .map_err(unavailable)?
+                        .0
+                }
+                _ => BTreeMap::new(),
+            };
+            self.entries = Some(entries);
+        }
+        Ok(self.entries.as_mut().unwrap())
+    }
+
+    /// Bytes taken by every entry except the one under `key`, which a put would replace
+    fn used_without(&mut self, key: &str) -> StorageResult<u64> {
+        Ok(self
+            .entries()?
+            .iter()
+            .filter(|(other, _)| *other != key)
+            .map(|(key, value)| entry_size(key, value))
+            .sum())
+    }
+
+    fn check_quota(&mut self, key: &str, value_len: u64) -> StorageResult<()> {
+        let used = self.used_without(key)?;
+        let size = (key.len() as u64).saturating_add(value_len);
+        if used.saturating_add(size) > self.quota {
+            return Err(StorageError::QuotaExceeded {
+                used,
+                quota: self.quota,
+            });
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> StorageResult<()> {
+        let (Some(path), Some(entries)) = (&self.path, &self.entries) else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(unavailable)?;
+        }
+
+        // Write to a temporary file first, so a crash never leaves a half written store behind
+        let bytes =
+            bincode::encode_to_vec(entries, bincode::config::standard()).map_err(unavailable)?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, bytes).map_err(unavailable)?;
+        fs::rename(&temporary, path).map_err(unavailable)
+    }
+}
+
+fn unavailable(error: impl std::fmt::Display) -> StorageError {
+    StorageError::Unavailable(format!("{}", error))
+}
+
+fn entry_size(key: &str, value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+/// Key-value entries of a single wrap, saved under its publisher and name
+///
+/// Entries are only read once the wrap first uses its storage, and saved after every change
+#[derive(Clone)]
+pub struct WrapStorage {
+    inner: Arc<Mutex<WrapStorageInner>>,
+}
+
+impl Default for WrapStorage {
+    fn default() -> Self {
+        Self::in_memory(0)
+    }
+}
+
+impl WrapStorage {
+    /// Storage for a wrap inside of `dir`, holding at most `quota` bytes of keys and values
+    pub fn open<P: AsRef<Path>>(dir: P, publisher: &str, name: &str, quota: u64) -> Self {
+        let path = dir
+            .as_ref()
+            .join(escape_file_name(publisher))
+            .join(escape_file_name(name))
+            .with_extension(STORAGE_EXTENSION);
+        Self::new(Some(path), quota)
+    }
+
+    /// Storage forgotten once the wrap is unloaded
+    pub fn in_memory(quota: u64) -> Self {
+        Self::new(None, quota)
+    }
+
+    fn new(path: Option<PathBuf>, quota: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(WrapStorageInner {
+                path,
+                entries: None,
+                quota,
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, WrapStorageInner> {
+        self.inner.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    pub fn get(&self, key: &str) -> StorageResult<Option<Vec<u8>>> {
+        check_key(key)?;
+        Ok(self.lock().entries()?.get(key).cloned())
+    }
+
+    /// Make sure a value of `value_len` bytes could be put under `key`, without having to read it
+    pub fn check_quota(&self, key: &str, value_len: u64) -> StorageResult<()> {
+        check_key(key)?;
+        self.lock().check_quota(key, value_len)
+    }
+
+    pub fn put(&self, key: &str, value: Vec<u8>) -> StorageResult<()> {
+        check_key(key)?;
+        let mut inner = self.lock();
+        inner.check_quota(key, value.len() as u64)?;
+        inner.entries()?.insert(key.into(), value);
+        inner.save()
+    }
+
+    pub fn delete(&self, key: &str) -> StorageResult<bool> {
+        check_key(key)?;
+        let mut inner = self.lock();
+        let existed = inner.entries()?.remove(key).is_some();
+        if existed {
+            inner.save()?;
+        }
+        Ok(existed)
+    }
+
+    pub fn list(&self, prefix: &str) -> StorageResult<Vec<String>> {
+        Ok(self
+            .lock()
+            .entries()?
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    /// Bytes taken by keys and values
+    pub fn used(&self) -> StorageResult<u64> {
+        Ok(self
+            .lock()
+            .entries()?
+            .iter()
+            .map(|(key, value)| entry_size(key, value))
+            .sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::test_dir;
+    use super::*;
+
+    #[test]
+    fn test_quota() {
+        let storage = WrapStorage::in_memory(10);
+        storage.put("key", vec![0; 4]).unwrap();
+        assert_eq!(storage.used().unwrap(), 7);
+
+        // Replacing an entry only counts its new size
+        storage.put("key", vec![0; 7]).unwrap();
+        assert_eq!(storage.used().unwrap(), 10);
+        assert_eq!(
+            storage.put("other", vec![0; 1]),
+            Err(StorageError::QuotaExceeded {
+                used: 10,
+                quota: 10
+            })
+        );
+        assert_eq!(
+            storage.check_quota("key", 8),
+            Err(StorageError::QuotaExceeded { used: 0, quota: 10 })
+        );
+        assert!(storage.check_quota("key", u64::MAX).is_err());
+
+        assert_eq!(storage.delete("key"), Ok(true));
+        assert_eq!(storage.used().unwrap(), 0);
+        assert_eq!(storage.check_quota("other", 5), Ok(()));
+    }
+
+    #[test]
+    fn test_escape_file_name() {
+        assert_eq!(escape_file_name("my_wrap-2"), "my_wrap-2");
+        assert_eq!(escape_file_name("../wrap"), "%2E%2E%2Fwrap");
+        assert_eq!(escape_file_name("a\\b c"), "a%5Cb%20c");
+
+        let dir = test_dir("storage-escape");
+        let storage = WrapStorage::open(&dir, "..", "../../escaped", 100);
+        storage.put("key", vec![1]).unwrap();
+        assert!(dir
+            .join("%2E%2E")
+            .join("%2E%2E%2F%2E%2E%2Fescaped.bin")
+            .exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_persistence() {
+        let dir = test_dir("storage-persistence");
+        let storage = WrapStorage::open(&dir, "publisher", "wrap", 100);
+        storage.put("kept", vec![1, 2, 3]).unwrap();
+        storage.put("deleted", vec![4]).unwrap();
+        storage.delete("deleted").unwrap();
+
+        let reopened = WrapStorage::open(&dir, "publisher", "wrap", 100);
+        assert_eq!(reopened.get("kept"), Ok(Some(vec![1, 2, 3])));
+        assert_eq!(reopened.get("deleted"), Ok(None));
+        assert_eq!(reopened.list(""), Ok(vec!["kept".into()]));
+
+        // Other wraps don't see the entries
+        let other = WrapStorage::open(&dir, "publisher", "other", 100);
+        assert_eq!(other.get("kept"), Ok(None));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_save() {
+        let dir = test_dir("storage-atomic");
+        let storage = WrapStorage::open(&dir, "publisher", "wrap", 100);
+        storage.put("key", vec![1]).unwrap();
+
+        let path = dir.join("publisher").join("wrap.bin");
+        assert!(path.exists());
+        assert!(!path.with_extension("tmp").exists());
+
+        // A temporary file left over by a crash is replaced by the next save, and never read
+        fs::write(path.with_extension("tmp"), b"garbage").unwrap();
+        storage.put("key", vec![2]).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+        let reopened = WrapStorage::open(&dir, "publisher", "wrap", 100);
+        assert_eq!(reopened.get("key"), Ok(Some(vec![2])));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use hmny_common::host::LogRecord;
+use std::fmt;
+use wasmer::{RuntimeError, TrapCode};
+
+/// Why a wrap stopped executing in the middle of a signal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrapKind {
+    /// Rust wraps abort through an unreachable instruction when they panic
+    Panic,
+    Unreachable,
+    /// The wrap accessed memory or a table outside of its bounds
+    OutOfBounds,
+    StackOverflow,
+    /// Integer overflow, division by zero or an invalid conversion
+    Arithmetic,
+    /// The wrap ran out of fuel before the signal completed
+    FuelExhausted,
+    /// The wrap trapped after its memory reached the maximum allowed size
+    MemoryLimitReached,
+    /// A host function called by the wrap failed
+    HostFunction,
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrapFrame {
+    /// Taken from the module's name section, if it has one
+    pub function: Option<String>,
+    pub function_index: u32,
+    pub module_offset: usize,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.function {
+            Some(function) => write!(f, "{}", function)?,
+            None => write!(f, "<function {}>", self.function_index)?,
+        }
+        write!(f, " @ {:#x}", self.module_offset)
+    }
+}
+
+/// Everything known about a trap, to report it better than the bare runtime error
+#[derive(Clone, Debug)]
+pub struct WrapTrap {
+    pub kind: TrapKind,
+    pub message: String,
+    /// Reported by the wrap's panic hook right before it trapped
+    pub panic: Option<LogRecord>,
+    /// Innermost frame first
+    pub backtrace: Vec<TrapFrame>,
+}
+
+impl WrapTrap {
+    pub fn new(error: &RuntimeError, kind: Option<TrapKind>, panic: Option<LogRecord>) -> Self {
+        let kind = kind.unwrap_or_else(|| match (error.clone().to_trap(), &panic) {
+            (Some(TrapCode::UnreachableCodeReached), Some(_)) => TrapKind::Panic,
+            (Some(TrapCode::UnreachableCodeReached), None) => TrapKind::Unreachable,
+            (Some(TrapCode::StackOverflow), _) => TrapKind::StackOverflow,
+            (
+                Some(
+                    TrapCode::HeapAccessOutOfBounds
+                    | TrapCode::HeapMisaligned
+                    | TrapCode::TableAccessOutOfBounds,
+                ),
+                _,
+            ) => TrapKind::OutOfBounds,
+            (
+                Some(
+                    TrapCode::IntegerOverflow
+                    | TrapCode::IntegerDivisionByZero
+                    | TrapCode::BadConversionToInteger,
+                ),
+                _,
+            ) => TrapKind::Arithmetic,
+            (Some(_), _) => TrapKind::Other,
+            // Errors that aren't traps were raised by host functions
+            (None, _) => TrapKind::HostFunction,
+        });
+
+        let backtrace = error
+            .trace()
+            .iter()
+            .map(|frame| TrapFrame {
+                function: frame.function_name().map(str::to_string),
+                function_index: frame.func_index(),
+                module_offset: frame.module_offset(),
+            })
+            .collect();
+
+        Self {
+            kind,
+            message: error.message(),
+            panic,
+            backtrace,
+        }
+    }
+
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(
+            self.kind,
+            TrapKind::FuelExhausted | TrapKind::MemoryLimitReached
+        )
+    }
+}
+
+impl fmt::Display for WrapTrap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)?;
+        if let Some(panic) = &self.panic {
+            write!(f, "\n  {}", panic.message)?;
+            if let (Some(file), Some(line)) = (&panic.file, panic.line) {
+                write!(f, " at {}:{}", file, line)?;
+            }
+        }
+        for (index, frame) in self.backtrace.iter().enumerate() {
+            write!(f, "\n  {}: {}", index, frame)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmny_common::host::LogLevel;
+    use wasmer::{imports, Instance, Module, Store};
+
+    /// Call the exported function `run` of a module, expecting it to trap
+    fn trap(wat: &str) -> RuntimeError {
+        let mut store = Store::default();
+        let module = Module::new(&store, wat).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        let run = instance.exports.get_function("run").unwrap();
+        run.call(&mut store, &[]).unwrap_err()
+    }
+
+    fn kind(error: &RuntimeError, panic: Option<LogRecord>) -> TrapKind {
+        WrapTrap::new(error, None, panic).kind
+    }
+
+    #[test]
+    fn test_classify_unreachable() {
+        let error = trap(r#"(module (func (export "run") unreachable))"#);
+        assert_eq!(kind(&error, None), TrapKind::Unreachable);
+
+        let panic = LogRecord {
+            level: LogLevel::Error,
+            message: "panicked".into(),
+            module_path: None,
+            file: Some("src/lib.rs".into()),
+            line: Some(1),
+        };
+        assert_eq!(kind(&error, Some(panic)), TrapKind::Panic);
+    }
+
+    #[test]
+    fn test_classify_traps() {
+        let error = trap(
+            r#"(module (memory 1) (func (export "run") (drop (i32.load (i32.const 65536)))))"#,
+        );
+        assert_eq!(kind(&error, None), TrapKind::OutOfBounds);
+
+        let error = trap(
+            r#"(module (func (export "run") (drop (i32.div_s (i32.const 1) (i32.const 0)))))"#,
+        );
+        assert_eq!(kind(&error, None), TrapKind::Arithmetic);
+
+        let error = trap(r#"(module (func $run (export "run") (call $run)))"#);
+        assert_eq!(kind(&error, None), TrapKind::StackOverflow);
+
+        let error = trap(
+            r#"(module
+                (type $void (func))
+                (table 1 funcref)
+                (func (export "run") (call_indirect (type $void) (i32.const 0))))"#,
+        );
+        assert_eq!(kind(&error, None), TrapKind::Other);
+    }
+
+    #[test]
+    fn test_classify_host_errors() {
+        let error = RuntimeError::new("Missing capability");
+        assert_eq!(kind(&error, None), TrapKind::HostFunction);
+
+        let trap = WrapTrap::new(&error, Some(TrapKind::FuelExhausted), None);
+        assert_eq!(trap.kind, TrapKind::FuelExhausted);
+        assert!(trap.is_limit_exceeded());
+        assert!(!WrapTrap::new(&error, None, None).is_limit_exceeded());
+    }
+}
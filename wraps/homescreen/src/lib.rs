@@ -10,7 +10,7 @@ impl HomescreenInterface for HomescreenWrap {
     fn homescreen_query(query: HomescreenQuery) -> HomescreenResult {
         match query {
             HomescreenQuery::AskHomeScreen => Ok(HomescreenResponse::HomeScreen {
-                mime_type: "markdown".into(),
+                mime_type: MimeType::new("text", "markdown"),
                 data: DataType::String(include_str!("../homescreen.md").into()),
             }),
         }
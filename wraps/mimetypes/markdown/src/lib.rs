@@ -4,7 +4,7 @@ mod mdast;
 
 #[define_wrap{
     publisher: Publisher::new("Harmony", vec![]),
-    wrap_type: WrapType::Mimetype("markdown".into()),
+    wrap_type: WrapType::Mimetype(MimeType::new("text", "markdown")),
 }]
 struct HomescreenWrap(CommonQuery, MimetypeQuery);
 
@@ -20,9 +20,12 @@ fn parse(data: DataType) -> MimetypeResult {
     // Markdown must be string
     let data = match data {
         DataType::String(data) => data,
-        // _ => return Err("Invalid data type".into()),
+        data => String::from_utf8(data.into_bytes()?)
+            .map_err(|_| WrapError::Other("Markdown must be valid utf-8".into()))?,
     };
 
+    wrap_debug!("Parsing {} bytes of markdown", data.len());
+
     // Parse markdown and produce dimension
     let dimension = markdown::to_mdast(&data, &markdown::ParseOptions::default())
         .and_then(|mdast| match mdast {
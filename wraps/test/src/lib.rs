@@ -1,15 +1,31 @@
 use hmny_common::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Kept across reloads through snapshots
+static PING_COUNT: AtomicU64 = AtomicU64::new(0);
 
 #[define_wrap{
     publisher: Publisher::new("Harmony", vec![]),
     wrap_type: WrapType::Test,
+    description: "Answers pings, to test the host",
+    homepage: "https://github.com/MarcGuiselin/hmny",
     common_query: match query {
         CommonQuery::Ping { message } => ping(message),
+        CommonQuery::Snapshot => snapshot(),
+        CommonQuery::Restore { state } => restore(state),
     }
 }]
 struct TestWrap(CommonQuery);
 
 fn ping(message: String) -> CommonResult {
+    let count = PING_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    wrap_info!(
+        "Pinged at {}ms with {:?}, {} pings so far",
+        host::now(),
+        message,
+        count
+    );
+
     let response = format!(
         r#"Greetings "{}"! I am {}, the wrap. Pleasure to meet you :)"#,
         message, WRAP_NAME
@@ -17,3 +33,17 @@ fn ping(message: String) -> CommonResult {
 
     Ok(CommonResponse::Pong { response })
 }
+
+fn snapshot() -> CommonResult {
+    let state = PING_COUNT.load(Ordering::Relaxed).to_le_bytes().to_vec();
+    Ok(CommonResponse::Snapshot { state })
+}
+
+fn restore(state: Vec<u8>) -> CommonResult {
+    let count = state
+        .try_into()
+        .map(u64::from_le_bytes)
+        .map_err(|_| WrapError::Other("Invalid snapshot".into()))?;
+    PING_COUNT.store(count, Ordering::Relaxed);
+    Ok(CommonResponse::Restored)
+}